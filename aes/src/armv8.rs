@@ -0,0 +1,48 @@
+// ARMv8 Cryptography Extensions backend (aarch64), installed by
+// `AES128::with_backend` only after the CPU reported AES support.
+
+use core::arch::aarch64::*;
+
+pub(crate) fn is_supported() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes")
+}
+
+pub(crate) fn encrypt_block(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    // SAFETY: this function is only reachable when `is_supported()` returned true.
+    unsafe { encrypt_block_armv8(expanded_key, block) }
+}
+
+pub(crate) fn decrypt_block(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    // SAFETY: this function is only reachable when `is_supported()` returned true.
+    unsafe { decrypt_block_armv8(expanded_key, block) }
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+unsafe fn round_key(expanded_key: &[[u8; 4]; 44], round: usize) -> uint8x16_t {
+    vld1q_u8(expanded_key[round * 4..].as_ptr() as *const u8)
+}
+
+// AESE is AddRoundKey + SubBytes + ShiftRows, so the round key is applied
+// before the substitution and the last key is XORed separately.
+#[target_feature(enable = "aes,neon")]
+unsafe fn encrypt_block_armv8(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    let mut state = vld1q_u8(block.as_ptr());
+    for round in 0..9 {
+        state = vaesmcq_u8(vaeseq_u8(state, round_key(expanded_key, round)));
+    }
+    state = vaeseq_u8(state, round_key(expanded_key, 9));
+    state = veorq_u8(state, round_key(expanded_key, 10));
+    vst1q_u8(block.as_mut_ptr(), state);
+}
+
+#[target_feature(enable = "aes,neon")]
+unsafe fn decrypt_block_armv8(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    let mut state = vld1q_u8(block.as_ptr());
+    state = vaesdq_u8(state, round_key(expanded_key, 10));
+    for round in (1..10).rev() {
+        state = vaesdq_u8(vaesimcq_u8(state), vaesimcq_u8(round_key(expanded_key, round)));
+    }
+    state = veorq_u8(state, round_key(expanded_key, 0));
+    vst1q_u8(block.as_mut_ptr(), state);
+}
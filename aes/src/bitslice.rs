@@ -0,0 +1,232 @@
+// Constant-time AES S-box on bitsliced data.
+//
+// `q[j]` holds bit j of up to 32 bytes, one byte per bit lane, so a single call
+// substitutes every lane without any secret-dependent memory access.
+// The circuit is the Boyar-Peralta S-box (as used by BearSSL's aes_ct).
+
+pub(crate) fn sbox(q: &mut [u32; 8]) {
+    let x0 = q[7];
+    let x1 = q[6];
+    let x2 = q[5];
+    let x3 = q[4];
+    let x4 = q[3];
+    let x5 = q[2];
+    let x6 = q[1];
+    let x7 = q[0];
+
+    // Top linear transformation.
+    let y14 = x3 ^ x5;
+    let y13 = x0 ^ x6;
+    let y9 = x0 ^ x3;
+    let y8 = x0 ^ x5;
+    let t0 = x1 ^ x2;
+    let y1 = t0 ^ x7;
+    let y4 = y1 ^ x3;
+    let y12 = y13 ^ y14;
+    let y2 = y1 ^ x0;
+    let y5 = y1 ^ x6;
+    let y3 = y5 ^ y8;
+    let t1 = x4 ^ y12;
+    let y15 = t1 ^ x5;
+    let y20 = t1 ^ x1;
+    let y6 = y15 ^ x7;
+    let y10 = y15 ^ t0;
+    let y11 = y20 ^ y9;
+    let y7 = x7 ^ y11;
+    let y17 = y10 ^ y11;
+    let y19 = y10 ^ y8;
+    let y16 = t0 ^ y11;
+    let y21 = y13 ^ y16;
+    let y18 = x0 ^ y16;
+
+    // Non-linear section.
+    let t2 = y12 & y15;
+    let t3 = y3 & y6;
+    let t4 = t3 ^ t2;
+    let t5 = y4 & x7;
+    let t6 = t5 ^ t2;
+    let t7 = y13 & y16;
+    let t8 = y5 & y1;
+    let t9 = t8 ^ t7;
+    let t10 = y2 & y7;
+    let t11 = t10 ^ t7;
+    let t12 = y9 & y11;
+    let t13 = y14 & y17;
+    let t14 = t13 ^ t12;
+    let t15 = y8 & y10;
+    let t16 = t15 ^ t12;
+    let t17 = t4 ^ t14;
+    let t18 = t6 ^ t16;
+    let t19 = t9 ^ t14;
+    let t20 = t11 ^ t16;
+    let t21 = t17 ^ y20;
+    let t22 = t18 ^ y19;
+    let t23 = t19 ^ y21;
+    let t24 = t20 ^ y18;
+
+    let t25 = t21 ^ t22;
+    let t26 = t21 & t23;
+    let t27 = t24 ^ t26;
+    let t28 = t25 & t27;
+    let t29 = t28 ^ t22;
+    let t30 = t23 ^ t24;
+    let t31 = t22 ^ t26;
+    let t32 = t31 & t30;
+    let t33 = t32 ^ t24;
+    let t34 = t23 ^ t33;
+    let t35 = t27 ^ t33;
+    let t36 = t24 & t35;
+    let t37 = t36 ^ t34;
+    let t38 = t27 ^ t36;
+    let t39 = t29 & t38;
+    let t40 = t25 ^ t39;
+
+    let t41 = t40 ^ t37;
+    let t42 = t29 ^ t33;
+    let t43 = t29 ^ t40;
+    let t44 = t33 ^ t37;
+    let t45 = t42 ^ t41;
+    let z0 = t44 & y15;
+    let z1 = t37 & y6;
+    let z2 = t33 & x7;
+    let z3 = t43 & y16;
+    let z4 = t40 & y1;
+    let z5 = t29 & y7;
+    let z6 = t42 & y11;
+    let z7 = t45 & y17;
+    let z8 = t41 & y10;
+    let z9 = t44 & y12;
+    let z10 = t37 & y3;
+    let z11 = t33 & y4;
+    let z12 = t43 & y13;
+    let z13 = t40 & y5;
+    let z14 = t29 & y2;
+    let z15 = t42 & y9;
+    let z16 = t45 & y14;
+    let z17 = t41 & y8;
+
+    // Bottom linear transformation.
+    let t46 = z15 ^ z16;
+    let t47 = z10 ^ z11;
+    let t48 = z5 ^ z13;
+    let t49 = z9 ^ z10;
+    let t50 = z2 ^ z12;
+    let t51 = z2 ^ z5;
+    let t52 = z7 ^ z8;
+    let t53 = z0 ^ z3;
+    let t54 = z6 ^ z7;
+    let t55 = z16 ^ z17;
+    let t56 = z12 ^ t48;
+    let t57 = t50 ^ t53;
+    let t58 = z4 ^ t46;
+    let t59 = z3 ^ t54;
+    let t60 = t46 ^ t57;
+    let t61 = z14 ^ t57;
+    let t62 = t52 ^ t58;
+    let t63 = t49 ^ t58;
+    let t64 = z4 ^ t59;
+    let t65 = t61 ^ t62;
+    let t66 = z1 ^ t63;
+    let s0 = t59 ^ t63;
+    let s6 = t56 ^ !t62;
+    let s7 = t48 ^ !t60;
+    let t67 = t64 ^ t65;
+    let s3 = t53 ^ t66;
+    let s4 = t51 ^ t66;
+    let s5 = t47 ^ t65;
+    let s1 = t64 ^ !s3;
+    let s2 = t55 ^ !t67;
+
+    q[7] = s0;
+    q[6] = s1;
+    q[5] = s2;
+    q[4] = s3;
+    q[3] = s4;
+    q[2] = s5;
+    q[1] = s6;
+    q[0] = s7;
+}
+
+// Inverse S-box: iS(y) = B(S(B(y) ^ 0x05)) ^ 0x05, where B is the linear part of
+// the inverse affine transform. Reusing the forward circuit keeps a single
+// non-linear implementation to audit.
+pub(crate) fn inv_sbox(q: &mut [u32; 8]) {
+    inv_affine(q);
+    sbox(q);
+    inv_affine(q);
+}
+
+// y -> B(y) ^ 0x05, with B(y)_i = y_(i+2) ^ y_(i+5) ^ y_(i+7).
+fn inv_affine(q: &mut [u32; 8]) {
+    let y = *q;
+    for i in 0..8 {
+        q[i] = y[(i + 2) % 8] ^ y[(i + 5) % 8] ^ y[(i + 7) % 8];
+    }
+    q[0] = !q[0];
+    q[2] = !q[2];
+}
+
+// Spread bytes into bit planes (lane i of plane j = bit j of bytes[i]).
+pub(crate) fn pack(bytes: &[u8]) -> [u32; 8] {
+    let mut q = [0u32; 8];
+    for (i, &byte) in bytes.iter().enumerate() {
+        for j in 0..8 {
+            q[j] |= (((byte >> j) & 1) as u32) << i;
+        }
+    }
+    q
+}
+
+// Gather bit planes back into bytes.
+pub(crate) fn unpack(q: &[u32; 8], bytes: &mut [u8]) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let mut b = 0u8;
+        for j in 0..8 {
+            b |= (((q[j] >> i) & 1) as u8) << j;
+        }
+        *byte = b;
+    }
+}
+
+// Substitute up to 32 bytes in place.
+pub(crate) fn sub_bytes(bytes: &mut [u8], encryption: bool) {
+    let mut q = pack(bytes);
+    if encryption {
+        sbox(&mut q);
+    } else {
+        inv_sbox(&mut q);
+    }
+    unpack(&q, bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack() {
+        let bytes: Vec<u8> = (0..32).map(|i| (i * 37 + 5) as u8).collect();
+        let q = pack(&bytes);
+        let mut out = vec![0u8; 32];
+        unpack(&q, &mut out);
+        assert_eq!(bytes, out);
+    }
+
+    #[test]
+    fn test_sbox_matches_table() {
+        for chunk in (0..=255u8).collect::<Vec<_>>().chunks(32) {
+            let mut bytes = chunk.to_vec();
+            sub_bytes(&mut bytes, true);
+            for (&x, &y) in chunk.iter().zip(bytes.iter()) {
+                assert_eq!(y, crate::AES_SBOX[(x >> 4) as usize][(x & 0xF) as usize]);
+            }
+            sub_bytes(&mut bytes, false);
+            assert_eq!(bytes, chunk);
+
+            sub_bytes(&mut bytes, false);
+            for (&x, &y) in chunk.iter().zip(bytes.iter()) {
+                assert_eq!(y, crate::INVERSE_AES_SBOX[(x >> 4) as usize][(x & 0xF) as usize]);
+            }
+        }
+    }
+}
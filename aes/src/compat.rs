@@ -0,0 +1,76 @@
+// Interoperability with the RustCrypto `cipher` traits (feature "cipher").
+
+use cipher::consts::U16;
+use cipher::generic_array::GenericArray;
+use cipher::{BlockDecrypt, BlockEncrypt, BlockSizeUser, Key, KeyInit, KeySizeUser};
+
+use crate::{BlockCipher, AES128};
+
+impl KeySizeUser for AES128 {
+    type KeySize = U16;
+}
+
+impl KeyInit for AES128 {
+    fn new(key: &Key<Self>) -> Self {
+        AES128::new(&(*key).into())
+    }
+}
+
+cipher::impl_simple_block_encdec!(
+    AES128, U16, aes, block,
+    encrypt: {
+        let mut bytes: [u8; 16] = (*block.get_in()).into();
+        BlockCipher::encrypt_block(aes, &mut bytes);
+        *block.get_out() = bytes.into();
+    }
+    decrypt: {
+        let mut bytes: [u8; 16] = (*block.get_in()).into();
+        BlockCipher::decrypt_block(aes, &mut bytes);
+        *block.get_out() = bytes.into();
+    }
+);
+
+// Wraps a RustCrypto block cipher so it can drive the modes of this crate.
+pub struct Compat<C>(pub C);
+
+impl<C> BlockCipher for Compat<C>
+where
+    C: BlockEncrypt + BlockDecrypt + BlockSizeUser<BlockSize = U16>,
+{
+    fn encrypt_block(&self, block: &mut [u8; 16]) {
+        BlockEncrypt::encrypt_block(&self.0, GenericArray::from_mut_slice(block));
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; 16]) {
+        BlockDecrypt::decrypt_block(&self.0, GenericArray::from_mut_slice(block));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cbc, Mode, Encryptor};
+
+    #[test]
+    fn test_cipher_traits_roundtrip() {
+        let key = AES128::generate_key();
+        let aes = <AES128 as KeyInit>::new(&key.into());
+        let mut block = GenericArray::from([0x42u8; 16]);
+        BlockEncrypt::encrypt_block(&aes, &mut block);
+        assert_eq!(block.as_slice(), AES128::new(&key).encrypt(&[0x42u8; 16]));
+        BlockDecrypt::decrypt_block(&aes, &mut block);
+        assert_eq!(block.as_slice(), &[0x42u8; 16]);
+    }
+
+    #[test]
+    fn test_modes_over_compat() {
+        let key = AES128::generate_key();
+        let iv = AES128::generate_iv();
+        let native = Cbc::new(AES128::new(&key)).encrypt(&iv, b"swap implementations");
+        let wrapped = Cbc::new(Compat(AES128::new(&key))).encrypt(&iv, b"swap implementations");
+        assert_eq!(native, wrapped);
+
+        let mut enc = Encryptor::new(Compat(AES128::new(&key)), Mode::Ctr(iv));
+        assert_eq!(enc.update(b"ctr"), crate::Ctr::new(AES128::new(&key)).apply(&iv, b"ctr"));
+    }
+}
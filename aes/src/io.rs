@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroize;
+
+use crate::stream::{Decryptor, Encryptor};
+use crate::{BlockCipher, AES128};
+
+const CHUNK_SIZE: usize = 8192;
+
+// A streaming cipher that can be driven by the Read/Write adapters below.
+pub trait Transform {
+    fn update(&mut self, input: &[u8]) -> Vec<u8>;
+    fn finish(&mut self) -> io::Result<Vec<u8>>;
+}
+
+impl<C: BlockCipher> Transform for Encryptor<C> {
+    fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        Encryptor::update(self, input)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Encryptor::finish(self))
+    }
+}
+
+impl<C: BlockCipher> Transform for Decryptor<C> {
+    fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        Decryptor::update(self, input)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Decryptor::finish(self)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid padding"))
+    }
+}
+
+// Writer that transforms everything written to it before passing it to `inner`.
+// `finish` must be called to flush the last block.
+pub struct CipherWriter<T: Transform, W: Write> {
+    inner: W,
+    transform: T,
+}
+
+impl<T: Transform, W: Write> CipherWriter<T, W> {
+    pub fn new(inner: W, transform: T) -> Self {
+        CipherWriter { inner, transform }
+    }
+
+    // Write the final block(s) and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let last = self.transform.finish()?;
+        self.inner.write_all(&last)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<T: Transform, W: Write> Write for CipherWriter<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let out = self.transform.update(buf);
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reader that pulls from `inner` and yields the transformed stream.
+pub struct CipherReader<T: Transform, R: Read> {
+    inner: R,
+    transform: T,
+    pending: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<T: Transform, R: Read> CipherReader<T, R> {
+    pub fn new(inner: R, transform: T) -> Self {
+        CipherReader {
+            inner,
+            transform,
+            pending: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<T: Transform, R: Read> Read for CipherReader<T, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.pending.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let mut chunk = [0u8; CHUNK_SIZE];
+            let n = self.inner.read(&mut chunk)?;
+            self.pending.zeroize();
+            self.pending = if n == 0 {
+                self.done = true;
+                self.transform.finish()?
+            } else {
+                self.transform.update(&chunk[..n])
+            };
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<T: Transform, R: Read> Drop for CipherReader<T, R> {
+    fn drop(&mut self) {
+        self.pending.zeroize();
+    }
+}
+
+pub type EncryptWriter<W, C = AES128> = CipherWriter<Encryptor<C>, W>;
+pub type DecryptWriter<W, C = AES128> = CipherWriter<Decryptor<C>, W>;
+pub type EncryptReader<R, C = AES128> = CipherReader<Encryptor<C>, R>;
+pub type DecryptReader<R, C = AES128> = CipherReader<Decryptor<C>, R>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::Mode;
+    use crate::AES128;
+
+    #[test]
+    fn test_writer_reader_roundtrip() {
+        let key = AES128::generate_key();
+        let iv = AES128::generate_iv();
+        let plaintext: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+
+        for mode in [Mode::Ctr(iv), Mode::Cbc(iv)] {
+            let mut writer = EncryptWriter::new(Vec::new(), Encryptor::new(AES128::new(&key), mode));
+            for chunk in plaintext.chunks(3000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let ciphertext = writer.finish().unwrap();
+
+            let mut reader = DecryptReader::new(&ciphertext[..], Decryptor::new(AES128::new(&key), mode));
+            let mut decrypted = Vec::new();
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut writer = DecryptWriter::new(Vec::new(), Decryptor::new(AES128::new(&key), mode));
+            writer.write_all(&ciphertext).unwrap();
+            assert_eq!(writer.finish().unwrap(), plaintext);
+
+            let mut reader = EncryptReader::new(&plaintext[..], Encryptor::new(AES128::new(&key), mode));
+            let mut reencrypted = Vec::new();
+            reader.read_to_end(&mut reencrypted).unwrap();
+            assert_eq!(reencrypted, ciphertext);
+        }
+    }
+
+    #[test]
+    fn test_reader_truncated() {
+        let key = AES128::generate_key();
+        let iv = [0u8; 16];
+        let mut reader = DecryptReader::new(&[0u8; 20][..], Decryptor::new(AES128::new(&key), Mode::Cbc(iv)));
+        let mut out = Vec::new();
+        let err = reader.read_to_end(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::stream::{Decryptor, Encryptor, Mode};
+use crate::BlockCipher;
+
+const BLOCK_SIZE: usize = 16;
+
+// Electronic codebook mode. Only meant for data that is already random-looking
+// (keys, single blocks); prefer CBC or CTR for anything else.
+pub struct Ecb<C: BlockCipher> {
+    cipher: C,
+}
+
+impl<C: BlockCipher> Ecb<C> {
+    pub fn new(cipher: C) -> Self {
+        Ecb { cipher }
+    }
+
+    // Panics unless the input is a multiple of 16 bytes.
+    pub fn encrypt(&self, bytes: &[u8]) -> Vec<u8> {
+        self.apply(bytes, |cipher, block| cipher.encrypt_block(block))
+    }
+
+    // Panics unless the input is a multiple of 16 bytes.
+    pub fn decrypt(&self, bytes: &[u8]) -> Vec<u8> {
+        self.apply(bytes, |cipher, block| cipher.decrypt_block(block))
+    }
+
+    fn apply(&self, bytes: &[u8], op: impl Fn(&C, &mut [u8; 16])) -> Vec<u8> {
+        if !bytes.len().is_multiple_of(BLOCK_SIZE) {
+            panic!("Input must be multiple of 16 bytes");
+        }
+        let mut res = bytes.to_vec();
+        for block in res.chunks_exact_mut(BLOCK_SIZE) {
+            let block: &mut [u8; 16] = block.try_into().unwrap();
+            op(&self.cipher, block);
+        }
+        res
+    }
+}
+
+// One-shot CBC with PKCS#7 padding. The IV is prepended to the ciphertext.
+pub struct Cbc<C: BlockCipher> {
+    cipher: C,
+}
+
+impl<C: BlockCipher> Cbc<C> {
+    pub fn new(cipher: C) -> Self {
+        Cbc { cipher }
+    }
+
+    // Returns IV || ciphertext.
+    pub fn encrypt(&self, iv: &[u8; 16], plaintext: &[u8]) -> Vec<u8> {
+        let mut enc = Encryptor::new(&self.cipher, Mode::Cbc(*iv));
+        let mut ciphertext = iv.to_vec();
+        ciphertext.extend(enc.update(plaintext));
+        ciphertext.extend(enc.finalize());
+        ciphertext
+    }
+
+    // Expects IV || ciphertext. Returns None on a bad length or bad padding.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < BLOCK_SIZE || !(ciphertext.len() - BLOCK_SIZE).is_multiple_of(BLOCK_SIZE) {
+            return None;
+        }
+        let iv: [u8; 16] = ciphertext[..BLOCK_SIZE].try_into().ok()?;
+        let mut dec = Decryptor::new(&self.cipher, Mode::Cbc(iv));
+        let mut plaintext = dec.update(&ciphertext[BLOCK_SIZE..]);
+        plaintext.extend(dec.finalize()?);
+        Some(plaintext)
+    }
+}
+
+// One-shot CTR with a 128-bit big-endian counter. Encryption and decryption are the same operation.
+pub struct Ctr<C: BlockCipher> {
+    cipher: C,
+}
+
+impl<C: BlockCipher> Ctr<C> {
+    pub fn new(cipher: C) -> Self {
+        Ctr { cipher }
+    }
+
+    pub fn apply(&self, counter: &[u8; 16], data: &[u8]) -> Vec<u8> {
+        let mut enc = Encryptor::new(&self.cipher, Mode::Ctr(*counter));
+        enc.update(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AES128;
+
+    // A toy cipher (XOR with a constant) to check the modes only rely on the trait.
+    struct XorCipher(u8);
+
+    impl BlockCipher for XorCipher {
+        fn encrypt_block(&self, block: &mut [u8; 16]) {
+            block.iter_mut().for_each(|b| *b ^= self.0);
+        }
+
+        fn decrypt_block(&self, block: &mut [u8; 16]) {
+            self.encrypt_block(block);
+        }
+    }
+
+    #[test]
+    fn test_modes_over_any_block_cipher() {
+        let plaintext = b"modes are layered on the BlockCipher trait";
+        let iv = [7u8; 16];
+
+        let cbc = Cbc::new(XorCipher(0x5a));
+        assert_eq!(cbc.decrypt(&cbc.encrypt(&iv, plaintext)).unwrap(), plaintext);
+
+        let ctr = Ctr::new(XorCipher(0x5a));
+        assert_eq!(ctr.apply(&iv, &ctr.apply(&iv, plaintext)), plaintext);
+
+        let ecb = Ecb::new(XorCipher(0x5a));
+        assert_eq!(ecb.encrypt(&[0u8; 32]), vec![0x5a; 32]);
+    }
+
+    #[test]
+    fn test_modes_borrow_cipher() {
+        let aes = AES128::new(&AES128::generate_key());
+        let iv = AES128::generate_iv();
+        let ciphertext = Cbc::new(&aes).encrypt(&iv, b"borrowed");
+        assert_eq!(&ciphertext[..16], &iv);
+        assert_eq!(aes.decrypt_cbc(&ciphertext).unwrap(), b"borrowed");
+        assert_eq!(Ecb::new(&aes).encrypt(&[0u8; 16]), aes.encrypt(&[0u8; 16]));
+    }
+}
@@ -0,0 +1,49 @@
+// AES-NI backend (x86_64), installed by `AES128::with_backend` only after the
+// CPU reported AES support through CPUID.
+
+use core::arch::x86_64::*;
+
+pub(crate) fn is_supported() -> bool {
+    std::is_x86_feature_detected!("aes") && std::is_x86_feature_detected!("sse2")
+}
+
+pub(crate) fn encrypt_block(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    // SAFETY: this function is only reachable when `is_supported()` returned true.
+    unsafe { encrypt_block_ni(expanded_key, block) }
+}
+
+pub(crate) fn decrypt_block(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    // SAFETY: this function is only reachable when `is_supported()` returned true.
+    unsafe { decrypt_block_ni(expanded_key, block) }
+}
+
+// The expanded key is 44 contiguous words, i.e. 11 round keys of 16 bytes in
+// the byte order AESENC expects.
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn round_key(expanded_key: &[[u8; 4]; 44], round: usize) -> __m128i {
+    _mm_loadu_si128(expanded_key[round * 4..].as_ptr() as *const __m128i)
+}
+
+#[target_feature(enable = "aes,sse2")]
+unsafe fn encrypt_block_ni(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    let mut state = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+    state = _mm_xor_si128(state, round_key(expanded_key, 0));
+    for round in 1..10 {
+        state = _mm_aesenc_si128(state, round_key(expanded_key, round));
+    }
+    state = _mm_aesenclast_si128(state, round_key(expanded_key, 10));
+    _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, state);
+}
+
+#[target_feature(enable = "aes,sse2")]
+unsafe fn decrypt_block_ni(expanded_key: &[[u8; 4]; 44], block: &mut [u8; 16]) {
+    let mut state = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+    state = _mm_xor_si128(state, round_key(expanded_key, 10));
+    for round in (1..10).rev() {
+        // Equivalent inverse cipher: middle round keys go through InvMixColumns.
+        state = _mm_aesdec_si128(state, _mm_aesimc_si128(round_key(expanded_key, round)));
+    }
+    state = _mm_aesdeclast_si128(state, round_key(expanded_key, 0));
+    _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, state);
+}
@@ -0,0 +1,83 @@
+use std::ops::{Deref, DerefMut};
+
+use zeroize::Zeroize;
+
+// Lock `len` bytes at `ptr` into RAM so they are never written to swap.
+// Best effort: this fails when RLIMIT_MEMLOCK is too low, which is not fatal.
+pub(crate) fn lock(ptr: *const u8, len: usize) -> bool {
+    len > 0 && unsafe { libc::mlock(ptr as *const libc::c_void, len) } == 0
+}
+
+pub(crate) fn unlock(ptr: *const u8, len: usize) {
+    unsafe {
+        libc::munlock(ptr as *const libc::c_void, len);
+    }
+}
+
+// Fixed-size heap buffer for keys and plaintext. It is mlock'ed while alive
+// (when the memlock limit allows it) and zeroized on drop. The length never
+// changes, so the data is never copied to a reallocated buffer.
+pub struct SecretBytes {
+    buf: Box<[u8]>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    pub fn zeroed(len: usize) -> SecretBytes {
+        let buf = vec![0u8; len].into_boxed_slice();
+        let locked = lock(buf.as_ptr(), buf.len());
+        SecretBytes { buf, locked }
+    }
+
+    // Copy `data` into a new secret buffer. The caller is responsible for wiping `data`.
+    pub fn from_slice(data: &[u8]) -> SecretBytes {
+        let mut secret = SecretBytes::zeroed(data.len());
+        secret.copy_from_slice(data);
+        secret
+    }
+
+    // Whether the buffer could be mlock'ed.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.buf.zeroize();
+        if self.locked {
+            unlock(self.buf.as_ptr(), self.buf.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_bytes() {
+        let mut secret = SecretBytes::zeroed(4096);
+        assert!(secret.iter().all(|&b| b == 0));
+        secret[..5].copy_from_slice(b"hello");
+        assert_eq!(&secret[..5], b"hello");
+        assert_eq!(secret.len(), 4096);
+
+        assert_eq!(&*SecretBytes::from_slice(b"key"), b"key");
+        assert!(!SecretBytes::zeroed(0).is_locked());
+    }
+}
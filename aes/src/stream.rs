@@ -0,0 +1,352 @@
+use zeroize::Zeroize;
+
+use crate::{unpad_pkcs7, BlockCipher, AES128};
+
+const BLOCK_SIZE: usize = 16;
+
+// Chaining mode and its initial value (counter block for CTR, IV for CBC).
+#[derive(Debug, Clone, Copy)]
+pub enum Mode {
+    Ctr([u8; 16]),
+    Cbc([u8; 16]),
+}
+
+// CTR keystream state, shared by the encryptor and the decryptor.
+struct CtrState {
+    counter: [u8; 16],
+    keystream: [u8; 16],
+    used: usize,
+}
+
+impl CtrState {
+    fn new(counter: [u8; 16]) -> CtrState {
+        CtrState {
+            counter,
+            keystream: [0u8; 16],
+            used: BLOCK_SIZE,
+        }
+    }
+
+    // XOR data with the keystream, refilling it one block at a time.
+    fn apply<C: BlockCipher>(&mut self, cipher: &C, data: &[u8], out: &mut Vec<u8>) {
+        for &byte in data {
+            if self.used == BLOCK_SIZE {
+                self.keystream = self.counter;
+                cipher.encrypt_block(&mut self.keystream);
+                increment_counter(&mut self.counter);
+                self.used = 0;
+            }
+            out.push(byte ^ self.keystream[self.used]);
+            self.used += 1;
+        }
+    }
+}
+
+impl Drop for CtrState {
+    fn drop(&mut self) {
+        self.keystream.zeroize();
+    }
+}
+
+// Increment the counter block as a 128-bit big-endian integer.
+fn increment_counter(counter: &mut [u8; 16]) {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+// CBC chaining state with the bytes of the block not yet processed.
+struct CbcState {
+    previous: [u8; 16],
+    buffer: Vec<u8>,
+}
+
+impl Drop for CbcState {
+    fn drop(&mut self) {
+        self.buffer.zeroize();
+    }
+}
+
+enum State {
+    Ctr(CtrState),
+    Cbc(CbcState),
+}
+
+impl State {
+    fn new(mode: Mode) -> State {
+        match mode {
+            Mode::Ctr(counter) => State::Ctr(CtrState::new(counter)),
+            Mode::Cbc(iv) => State::Cbc(CbcState {
+                previous: iv,
+                buffer: Vec::with_capacity(BLOCK_SIZE),
+            }),
+        }
+    }
+}
+
+// Incremental encryptor: feed arbitrary-sized chunks with `update`, then `finalize`.
+pub struct Encryptor<C: BlockCipher = AES128> {
+    cipher: C,
+    state: State,
+}
+
+impl<C: BlockCipher> Encryptor<C> {
+    pub fn new(cipher: C, mode: Mode) -> Self {
+        Encryptor {
+            cipher,
+            state: State::new(mode),
+        }
+    }
+
+    // Encrypt a chunk, returning the ciphertext available so far.
+    pub fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() + BLOCK_SIZE);
+        match &mut self.state {
+            State::Ctr(ctr) => ctr.apply(&self.cipher, input, &mut out),
+            State::Cbc(cbc) => {
+                cbc.buffer.extend_from_slice(input);
+                let full = cbc.buffer.len() - cbc.buffer.len() % BLOCK_SIZE;
+                for block in cbc.buffer[..full].chunks(BLOCK_SIZE) {
+                    cbc.previous = cbc_encrypt_block(&self.cipher, &cbc.previous, block);
+                    out.extend_from_slice(&cbc.previous);
+                }
+                cbc.buffer.drain(..full);
+            }
+        }
+        out
+    }
+
+    // Flush the remaining ciphertext. CBC pads the last block with PKCS#7.
+    pub fn finalize(mut self) -> Vec<u8> {
+        self.finish()
+    }
+
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        match &mut self.state {
+            State::Ctr(_) => Vec::new(),
+            State::Cbc(cbc) => {
+                let mut padded = crate::pad_pkcs7(&cbc.buffer, BLOCK_SIZE);
+                cbc.buffer.zeroize();
+                let mut out = Vec::with_capacity(padded.len());
+                for block in padded.chunks(BLOCK_SIZE) {
+                    cbc.previous = cbc_encrypt_block(&self.cipher, &cbc.previous, block);
+                    out.extend_from_slice(&cbc.previous);
+                }
+                padded.zeroize();
+                out
+            }
+        }
+    }
+}
+
+// Incremental decryptor: the counterpart of `Encryptor`.
+pub struct Decryptor<C: BlockCipher = AES128> {
+    cipher: C,
+    state: State,
+}
+
+impl<C: BlockCipher> Decryptor<C> {
+    pub fn new(cipher: C, mode: Mode) -> Self {
+        Decryptor {
+            cipher,
+            state: State::new(mode),
+        }
+    }
+
+    // Decrypt a chunk, returning the plaintext available so far.
+    // CBC holds back the last full block until `finalize` so the padding can be checked.
+    pub fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() + BLOCK_SIZE);
+        match &mut self.state {
+            State::Ctr(ctr) => ctr.apply(&self.cipher, input, &mut out),
+            State::Cbc(cbc) => {
+                cbc.buffer.extend_from_slice(input);
+                if cbc.buffer.len() > BLOCK_SIZE {
+                    let keep = match cbc.buffer.len() % BLOCK_SIZE {
+                        0 => BLOCK_SIZE,
+                        rem => rem,
+                    };
+                    let ready = cbc.buffer.len() - keep;
+                    for block in cbc.buffer[..ready].chunks(BLOCK_SIZE) {
+                        cbc_decrypt_block(&self.cipher, &mut cbc.previous, block, &mut out);
+                    }
+                    cbc.buffer.drain(..ready);
+                }
+            }
+        }
+        out
+    }
+
+    // Flush the remaining plaintext. Returns None if the CBC input was truncated or badly padded.
+    pub fn finalize(mut self) -> Option<Vec<u8>> {
+        self.finish()
+    }
+
+    pub(crate) fn finish(&mut self) -> Option<Vec<u8>> {
+        match &mut self.state {
+            State::Ctr(_) => Some(Vec::new()),
+            State::Cbc(cbc) => {
+                if cbc.buffer.len() != BLOCK_SIZE {
+                    return None;
+                }
+                let mut last = Vec::with_capacity(BLOCK_SIZE);
+                let block = std::mem::take(&mut cbc.buffer);
+                cbc_decrypt_block(&self.cipher, &mut cbc.previous, &block, &mut last);
+                let plaintext = unpad_pkcs7(&last);
+                last.zeroize();
+                plaintext
+            }
+        }
+    }
+}
+
+fn cbc_encrypt_block<C: BlockCipher>(cipher: &C, previous: &[u8; 16], block: &[u8]) -> [u8; 16] {
+    let mut xor_block = [0u8; 16];
+    for j in 0..BLOCK_SIZE {
+        xor_block[j] = block[j] ^ previous[j];
+    }
+    cipher.encrypt_block(&mut xor_block);
+    xor_block
+}
+
+fn cbc_decrypt_block<C: BlockCipher>(cipher: &C, previous: &mut [u8; 16], block: &[u8], out: &mut Vec<u8>) {
+    let mut arr = [0u8; 16];
+    arr.copy_from_slice(block);
+    let mut decrypted = arr;
+    cipher.decrypt_block(&mut decrypted);
+    for j in 0..BLOCK_SIZE {
+        out.push(decrypted[j] ^ previous[j]);
+    }
+    decrypted.zeroize();
+    *previous = arr;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // NIST SP 800-38A, F.5.1 and F.2.1 (AES-128).
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51\
+                             30c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+
+    fn aes() -> AES128 {
+        AES128::new(&hex(KEY).try_into().unwrap())
+    }
+
+    #[test]
+    fn test_ctr_nist_vector() {
+        let counter: [u8; 16] = hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").try_into().unwrap();
+        let expected = hex("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff\
+                            5ae4df3edbd5d35e5b4f09020db03eab1e031dda2fbe03d1792170a0f3009cee");
+        let mut enc = Encryptor::new(aes(), Mode::Ctr(counter));
+        let mut ciphertext = enc.update(&hex(PLAINTEXT));
+        ciphertext.extend(enc.finalize());
+        assert_eq!(ciphertext, expected);
+    }
+
+    #[test]
+    fn test_cbc_nist_vector() {
+        let iv: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f").try_into().unwrap();
+        let expected = hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2\
+                            73bed6b8e3c1743b7116e69e222295163ff1caa1681fac09120eca307586e1a7");
+        let mut enc = Encryptor::new(aes(), Mode::Cbc(iv));
+        let ciphertext = enc.update(&hex(PLAINTEXT));
+        assert_eq!(ciphertext, expected);
+        // The plaintext is block aligned, so finalize emits one full padding block.
+        assert_eq!(enc.finalize().len(), 16);
+    }
+
+    #[test]
+    fn test_counter_wraps() {
+        let mut counter = [0xff; 16];
+        increment_counter(&mut counter);
+        assert_eq!(counter, [0u8; 16]);
+
+        let mut counter = [0u8; 16];
+        counter[15] = 0xff;
+        increment_counter(&mut counter);
+        assert_eq!(counter[14..], [1, 0]);
+    }
+
+    #[test]
+    fn test_chunked_matches_one_shot() {
+        let key = AES128::generate_key();
+        let iv = AES128::generate_iv();
+        let plaintext: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+        for mode in [Mode::Ctr(iv), Mode::Cbc(iv)] {
+            let mut one_shot = Encryptor::new(AES128::new(&key), mode);
+            let mut expected = one_shot.update(&plaintext);
+            expected.extend(one_shot.finalize());
+
+            for chunk_size in [1, 5, 15, 16, 17, 100, 999] {
+                let mut enc = Encryptor::new(AES128::new(&key), mode);
+                let mut ciphertext = Vec::new();
+                for chunk in plaintext.chunks(chunk_size) {
+                    ciphertext.extend(enc.update(chunk));
+                }
+                ciphertext.extend(enc.finalize());
+                assert_eq!(ciphertext, expected);
+
+                let mut dec = Decryptor::new(AES128::new(&key), mode);
+                let mut decrypted = Vec::new();
+                for chunk in ciphertext.chunks(chunk_size) {
+                    decrypted.extend(dec.update(chunk));
+                }
+                decrypted.extend(dec.finalize().unwrap());
+                assert_eq!(decrypted, plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cbc_matches_encrypt_cbc() {
+        let aes = aes();
+        let plaintext = b"Hello World! This is a test message for CBC mode encryption.";
+        let ciphertext = aes.encrypt_cbc(plaintext);
+        let iv: [u8; 16] = ciphertext[..16].try_into().unwrap();
+
+        let mut dec = Decryptor::new(AES128::new(&hex(KEY).try_into().unwrap()), Mode::Cbc(iv));
+        let mut decrypted = dec.update(&ciphertext[16..]);
+        decrypted.extend(dec.finalize().unwrap());
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_cbc_decrypt_truncated() {
+        let iv = [0u8; 16];
+        let mut enc = Encryptor::new(aes(), Mode::Cbc(iv));
+        let mut ciphertext = enc.update(b"some secret payload");
+        ciphertext.extend(enc.finalize());
+
+        let mut dec = Decryptor::new(aes(), Mode::Cbc(iv));
+        dec.update(&ciphertext[..ciphertext.len() - 3]);
+        assert!(dec.finalize().is_none());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let iv = [0u8; 16];
+        let enc = Encryptor::new(aes(), Mode::Ctr(iv));
+        assert!(enc.finalize().is_empty());
+
+        let enc = Encryptor::new(aes(), Mode::Cbc(iv));
+        let ciphertext = enc.finalize();
+        assert_eq!(ciphertext.len(), 16);
+
+        let mut dec = Decryptor::new(aes(), Mode::Cbc(iv));
+        assert!(dec.update(&ciphertext).is_empty());
+        assert_eq!(dec.finalize().unwrap(), b"");
+    }
+}
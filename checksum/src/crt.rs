@@ -0,0 +1,217 @@
+//! Chinese remainder theorem helpers shared by the whitebox generator and decryptor.
+//!
+//! All products are computed in `i128`, so moduli up to `i64::MAX` are safe as long
+//! as the product of the basis still fits in an `i64`.
+
+use std::fmt;
+
+/// Reasons a set of moduli cannot be used as a CRT basis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrtError {
+    /// The basis has no moduli.
+    Empty,
+    /// A modulus is lower than 2.
+    InvalidModulus(i64),
+    /// Two moduli share a common factor.
+    NotCoprime(i64, i64),
+    /// The product of the moduli does not fit in an `i64`.
+    Overflow,
+}
+
+impl fmt::Display for CrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrtError::Empty => write!(f, "empty CRT basis"),
+            CrtError::InvalidModulus(m) => write!(f, "invalid CRT modulus {}", m),
+            CrtError::NotCoprime(a, b) => write!(f, "CRT moduli {} and {} are not coprime", a, b),
+            CrtError::Overflow => write!(f, "product of the CRT basis overflows i64"),
+        }
+    }
+}
+
+impl std::error::Error for CrtError {}
+
+/// A validated CRT basis with its reconstruction coefficients precomputed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrtBasis {
+    moduli: Vec<i64>,
+    product: i64,
+    // coefficients[i] = (M / m_i) * ((M / m_i)^-1 mod m_i) mod M
+    coefficients: Vec<i64>,
+}
+
+impl CrtBasis {
+    /// Validate `moduli` (each >= 2, pairwise coprime, product fits in i64) and precompute
+    /// the reconstruction coefficients.
+    pub fn new(moduli: &[i64]) -> Result<CrtBasis, CrtError> {
+        if moduli.is_empty() {
+            return Err(CrtError::Empty);
+        }
+        let mut product: i64 = 1;
+        for (i, &m) in moduli.iter().enumerate() {
+            if m < 2 {
+                return Err(CrtError::InvalidModulus(m));
+            }
+            for &other in &moduli[..i] {
+                if gcd(m, other) != 1 {
+                    return Err(CrtError::NotCoprime(other, m));
+                }
+            }
+            product = product.checked_mul(m).ok_or(CrtError::Overflow)?;
+        }
+
+        let coefficients = moduli
+            .iter()
+            .map(|&m| {
+                let partial = product / m;
+                // Cannot fail: the moduli are pairwise coprime.
+                let inverse = mod_inverse(partial, m).ok_or(CrtError::NotCoprime(partial, m))?;
+                Ok(mul_mod(partial, inverse, product))
+            })
+            .collect::<Result<Vec<i64>, CrtError>>()?;
+
+        Ok(CrtBasis {
+            moduli: moduli.to_vec(),
+            product,
+            coefficients,
+        })
+    }
+
+    pub fn moduli(&self) -> &[i64] {
+        &self.moduli
+    }
+
+    /// Product of all the moduli.
+    pub fn product(&self) -> i64 {
+        self.product
+    }
+
+    /// Residues of `x` modulo each modulus of the basis.
+    pub fn to_residues(&self, x: i64) -> Vec<i64> {
+        goto_crt(x, &self.moduli)
+    }
+
+    /// The unique value in `[0, product)` with the given residues.
+    /// Residues do not need to be reduced beforehand.
+    ///
+    /// # Panics
+    /// Panics if `residues` does not have one entry per modulus.
+    pub fn reconstruct(&self, residues: &[i64]) -> i64 {
+        assert_eq!(residues.len(), self.moduli.len(), "CRT residue count does not match the basis");
+        let product = self.product as i128;
+        let mut x: i128 = 0;
+        for ((&r, &m), &c) in residues.iter().zip(&self.moduli).zip(&self.coefficients) {
+            let r = r.rem_euclid(m) as i128;
+            x = (x + r * c as i128) % product;
+        }
+        x as i64
+    }
+}
+
+/// Reconstruct `x` from its residues over `base`.
+///
+/// Panics if `base` is not a valid basis. Build a [`CrtBasis`] once instead when
+/// reconstructing many values over the same moduli.
+pub fn goback_crt(x_b: &[i64], base: &[i64]) -> i64 {
+    match CrtBasis::new(base) {
+        Ok(basis) => basis.reconstruct(x_b),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Residues of `x` modulo each element of `base`.
+pub fn goto_crt(x: i64, base: &[i64]) -> Vec<i64> {
+    base.iter().map(|&b| x.rem_euclid(b)).collect()
+}
+
+/// `a * b mod m`, in `[0, m)`, without intermediate overflow.
+pub fn mul_mod(a: i64, b: i64, m: i64) -> i64 {
+    ((a as i128 * b as i128).rem_euclid(m as i128)) as i64
+}
+
+/// Inverse of `a` modulo `m`, in `[0, m)`, or None if they are not coprime.
+pub fn mod_inverse(a: i64, m: i64) -> Option<i64> {
+    if m < 1 {
+        return None;
+    }
+    let (g, x, _) = xgcd(a.rem_euclid(m) as i128, m as i128);
+    if g != 1 {
+        return None;
+    }
+    Some(x.rem_euclid(m as i128) as i64)
+}
+
+fn gcd(mut a: i64, mut b: i64) -> i64 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a.abs()
+}
+
+// Extended Euclidean algorithm: returns (g, x, y) with a*x + b*y = g.
+fn xgcd(mut a: i128, mut b: i128) -> (i128, i128, i128) {
+    let (mut x0, mut x1) = (1i128, 0i128);
+    let (mut y0, mut y1) = (0i128, 1i128);
+    while b != 0 {
+        let q = a / b;
+        (a, b) = (b, a - q * b);
+        (x0, x1) = (x1, x0 - q * x1);
+        (y0, y1) = (y1, y0 - q * y1);
+    }
+    (a, x0, y0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_basis_roundtrip() {
+        let basis = CrtBasis::new(&[13, 16, 19, 27, 29]).unwrap();
+        assert_eq!(basis.product(), 13 * 16 * 19 * 27 * 29);
+        for x in [0, 1, 1231872, basis.product() - 1] {
+            assert_eq!(basis.reconstruct(&basis.to_residues(x)), x);
+        }
+    }
+
+    #[test]
+    fn test_large_moduli_do_not_overflow() {
+        // The product is ~1e18: x_i * (M / m_i) * inverse overflows i64 arithmetic.
+        let basis = CrtBasis::new(&[1_000_000_007, 998_244_353]).unwrap();
+        for x in [0, 123_456_789_012_345_678, basis.product() - 1] {
+            assert_eq!(basis.reconstruct(&basis.to_residues(x)), x);
+        }
+    }
+
+    #[test]
+    fn test_unreduced_residues() {
+        let basis = CrtBasis::new(&[3, 5, 7]).unwrap();
+        assert_eq!(basis.reconstruct(&[2 + 3, 3 - 5, 2 + 14]), 23);
+    }
+
+    #[test]
+    fn test_invalid_basis() {
+        assert_eq!(CrtBasis::new(&[]), Err(CrtError::Empty));
+        assert_eq!(CrtBasis::new(&[5, 1]), Err(CrtError::InvalidModulus(1)));
+        assert_eq!(CrtBasis::new(&[6, 7, 10]), Err(CrtError::NotCoprime(6, 10)));
+        assert_eq!(CrtBasis::new(&[i64::MAX, 2]), Err(CrtError::Overflow));
+    }
+
+    #[test]
+    fn test_mod_inverse() {
+        assert_eq!(mod_inverse(3, 11), Some(4));
+        assert_eq!(mod_inverse(-3, 11), Some(7));
+        assert_eq!(mod_inverse(6, 9), None);
+        assert_eq!(mod_inverse(1231872, 1231873), Some(1231872));
+        let m = 1_000_000_007;
+        assert_eq!(mul_mod(mod_inverse(123_456_789, m).unwrap(), 123_456_789, m), 1);
+    }
+
+    #[test]
+    fn test_mul_mod() {
+        assert_eq!(mul_mod(i64::MAX, i64::MAX, 1_000_000_007), ((i64::MAX as i128 * i64::MAX as i128) % 1_000_000_007) as i64);
+        assert_eq!(mul_mod(-2, 3, 7), 1);
+    }
+}
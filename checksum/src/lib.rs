@@ -1,5 +1,8 @@
 use blake3::Hasher;
 
+pub mod crt;
+pub mod signature;
+
 /// Compute Blake3 hash of data
 pub fn compute_blake3(data: &[u8]) -> [u8; 32] {
     let mut hasher = Hasher::new();
@@ -13,6 +16,16 @@ pub fn validate_blake3(data: &[u8], expected: &[u8; 32]) -> bool {
     computed == *expected
 }
 
+/// Compute the keyed Blake3 hash (MAC) of data
+pub fn compute_keyed_blake3(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    *blake3::keyed_hash(key, data).as_bytes()
+}
+
+/// Validate a keyed Blake3 hash in constant time
+pub fn validate_keyed_blake3(key: &[u8; 32], data: &[u8], expected: &[u8; 32]) -> bool {
+    blake3::keyed_hash(key, data) == blake3::Hash::from(*expected)
+}
+
 /// CRT-specific checksum for vector verification using Blake3
 pub fn compute_crt_checksum(values: &[i64]) -> [u8; 32] {
     let bytes: Vec<u8> = values.iter()
@@ -53,42 +66,12 @@ pub fn verify_crt_operation(original: i64, base: &[i64], expected_crt: &[i64]) -
 
 /// CRT conversion
 pub fn goto_crt(x: i64, base: &[i64]) -> Vec<i64> {
-    base.iter().map(|&b| x.rem_euclid(b)).collect()
+    crt::goto_crt(x, base)
 }
 
 /// CRT reconstruction
 pub fn goback_crt(x_b: &[i64], base: &[i64]) -> i64 {
-    let mut x = 0;
-    let b_prod: i64 = base.iter().product();
-    for i in 0..base.len() {
-        let b_i = b_prod / base[i];
-        let (_, mi, _) = xgcd(b_i, base[i]);
-        x = (x + (x_b[i] * b_i % b_prod).rem_euclid(b_prod) * mi % b_prod).rem_euclid(b_prod);
-    }
-    x.rem_euclid(b_prod)
-}
-
-/// Extended Euclidean algorithm for CRT
-fn xgcd(mut b: i64, mut n: i64) -> (i64, i64, i64) {
-    let mut x0 = 1;
-    let mut x1 = 0;
-    let mut y0 = 0;
-    let mut y1 = 1;
-    while n != 0 {
-        let q = b / n;
-        let tmp_n = n;
-        n = b % n;
-        b = tmp_n;
-
-        let tmp_x1 = x1;
-        x1 = x0 - q * x1;
-        x0 = tmp_x1;
-
-        let tmp_y1 = y1;
-        y1 = y0 - q * y1;
-        y0 = tmp_y1;
-    }
-    (b, x0, y0)
+    crt::goback_crt(x_b, base)
 }
 
 /// Format Blake3 hash as hex string
@@ -133,6 +116,14 @@ mod tests {
         assert!(!validate_blake3(data, &wrong_hash));
     }
 
+    #[test]
+    fn test_validate_keyed_blake3() {
+        let mac = compute_keyed_blake3(&[1; 32], b"hello");
+        assert_ne!(mac, compute_blake3(b"hello"));
+        assert!(validate_keyed_blake3(&[1; 32], b"hello", &mac));
+        assert!(!validate_keyed_blake3(&[2; 32], b"hello", &mac));
+    }
+
     #[test]
     fn test_integer_sequence_checksum() {
         let values = [1, 2, 3];
@@ -190,6 +181,12 @@ mod tests {
         assert_ne!(reconstructed, x);
     }
 
+    #[test]
+    #[should_panic(expected = "not coprime")]
+    fn test_crt_non_coprime_base() {
+        goback_crt(&[1, 1], &[4, 6]);
+    }
+
     #[test]
     fn test_verify_crt_operation_correct() {
         let original = 7;
@@ -0,0 +1,66 @@
+//! Ed25519 signatures used to authenticate packed images.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+pub const SECRET_KEY_SIZE: usize = 32;
+pub const PUBLIC_KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Public key matching `secret_key`.
+pub fn public_key(secret_key: &[u8; SECRET_KEY_SIZE]) -> [u8; PUBLIC_KEY_SIZE] {
+    SigningKey::from_bytes(secret_key).verifying_key().to_bytes()
+}
+
+/// Sign `message` with `secret_key`.
+pub fn sign(secret_key: &[u8; SECRET_KEY_SIZE], message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+    SigningKey::from_bytes(secret_key).sign(message).to_bytes()
+}
+
+/// Check `signature` over `message`. Invalid keys, non-canonical and malleable
+/// signatures are rejected.
+pub fn verify(public_key: &[u8; PUBLIC_KEY_SIZE], message: &[u8], signature: &[u8; SIGNATURE_SIZE]) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key.verify_strict(message, &Signature::from_bytes(signature)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let secret = [7u8; SECRET_KEY_SIZE];
+        let public = public_key(&secret);
+        let signature = sign(&secret, b"packed image");
+        assert!(verify(&public, b"packed image", &signature));
+        assert!(!verify(&public, b"packed imagf", &signature));
+        assert!(!verify(&public_key(&[8u8; SECRET_KEY_SIZE]), b"packed image", &signature));
+
+        let mut forged = signature;
+        forged[0] ^= 1;
+        assert!(!verify(&public, b"packed image", &forged));
+    }
+
+    #[test]
+    fn test_rfc8032_vector() {
+        // RFC 8032, section 7.1, test 1 (empty message)
+        let secret: [u8; 32] = hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let public: [u8; 32] = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let signature: [u8; 64] = hex(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        );
+        assert_eq!(public_key(&secret), public);
+        assert_eq!(sign(&secret, b""), signature);
+        assert!(verify(&public, b"", &signature));
+    }
+
+    fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+}
@@ -0,0 +1,300 @@
+//! Audit mode, enabled by `rpack --audit`: instead of reacting to detections, the stub
+//! records the result of each check as a JSON line and keeps running.
+//! `rpack audit-summary` tallies the records, to see how often legitimate users would
+//! trip the checks before shipping a hardened build.
+//!
+//! Records name checks by category and index rather than by name, so that production
+//! stubs carry no description of their checks; [`check_name`] translates.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Syslog socket used by `--audit syslog`.
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Environment checks, in the order the stub runs them.
+pub const ENVIRONMENT_CHECKS: [&str; 16] = [
+    "hypervisor flag",
+    "VM files",
+    "MAC address",
+    "disk size",
+    "uptime",
+    "CPUID vendor",
+    "virtio devices",
+    "DMI strings",
+    "CPUID timing",
+    "container files",
+    "container cgroup",
+    "container variable",
+    "emulated CPU",
+    "QEMU variables",
+    "missing vDSO",
+    "frozen TSC",
+];
+
+/// Debugger checks, in the order the stub runs them.
+pub const DEBUGGER_CHECKS: [&str; 6] = [
+    "tracer",
+    "PTRACE_TRACEME",
+    "LD_PRELOAD/LD_AUDIT",
+    "parent process",
+    "debug registers",
+    "SIGTRAP handler",
+];
+
+/// Where the stub writes its records. Written on the command line as `file:PATH`,
+/// `syslog` or `syslog:SOCKET`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditTarget {
+    /// Append to this file, created if needed.
+    File(String),
+    /// Send to the syslog daemon listening on this Unix datagram socket.
+    Syslog(String),
+}
+
+impl FromStr for AuditTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "syslog" => Ok(AuditTarget::Syslog(DEFAULT_SYSLOG_SOCKET.into())),
+            Some(("syslog", socket)) if !socket.is_empty() => Ok(AuditTarget::Syslog(socket.into())),
+            Some(("file", path)) if !path.is_empty() => Ok(AuditTarget::File(path.into())),
+            _ => Err(format!("unknown audit target '{}' (expected file:PATH, syslog or syslog:SOCKET)", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuditTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditTarget::File(path) => write!(f, "file:{}", path),
+            AuditTarget::Syslog(socket) => write!(f, "syslog:{}", socket),
+        }
+    }
+}
+
+/// What a weighted check looks for. Each category has its own score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Vm,
+    Container,
+    Emulator,
+    Debugger,
+}
+
+impl Category {
+    /// Name in the JSON records.
+    pub fn id(self) -> &'static str {
+        match self {
+            Category::Vm => "vm",
+            Category::Container => "container",
+            Category::Emulator => "emulator",
+            Category::Debugger => "debugger",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Vm => write!(f, "VM"),
+            other => write!(f, "{}", other.id()),
+        }
+    }
+}
+
+/// Name of check `index` of `category`.
+pub fn check_name(category: Category, index: usize) -> Option<&'static str> {
+    match category {
+        Category::Debugger => DEBUGGER_CHECKS.get(index).copied(),
+        _ => ENVIRONMENT_CHECKS.get(index).copied(),
+    }
+}
+
+/// What the stub's detection codes (E1xx) stand for.
+pub fn detection_name(code: u16) -> Option<&'static str> {
+    Some(match code {
+        101 => "tracer",
+        102 => "timing",
+        103 => "timing delay",
+        104 => "VM",
+        105 => "code modified in memory",
+        106 => "breakpoint",
+        107 => "debugger",
+        108 => "container",
+        109 => "emulator",
+        _ => return None,
+    })
+}
+
+/// One record of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Unix time, in seconds.
+    pub time: u64,
+    pub pid: u32,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum EventKind {
+    /// The packed binary started.
+    Start,
+    /// Result of one weighted check, see [`check_name`].
+    Check { category: Category, check: usize, weight: f32, fired: bool },
+    /// Total weight of the checks of a category that fired.
+    Score { category: Category, score: f32, threshold: f32 },
+    /// A detection, with the stub's failure code, that the reaction would have handled.
+    Detection { code: u16 },
+}
+
+impl fmt::Display for Event {
+    /// One JSON line, without a JSON library in the stub.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"time\":{},\"pid\":{},", self.time, self.pid)?;
+        match &self.kind {
+            EventKind::Start => write!(f, "\"event\":\"start\"}}"),
+            EventKind::Check { category, check, weight, fired } => write!(
+                f,
+                "\"event\":\"check\",\"category\":\"{}\",\"check\":{},\"weight\":{:?},\"fired\":{}}}",
+                category.id(),
+                check,
+                weight,
+                fired
+            ),
+            EventKind::Score { category, score, threshold } => write!(
+                f,
+                "\"event\":\"score\",\"category\":\"{}\",\"score\":{:?},\"threshold\":{:?}}}",
+                category.id(),
+                score,
+                threshold
+            ),
+            EventKind::Detection { code } => write!(f, "\"event\":\"detection\",\"code\":{}}}", code),
+        }
+    }
+}
+
+/// How often each check fired, each score reached its threshold and each detection
+/// happened, over a set of records.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub runs: usize,
+    /// Times fired and times run, with the weight, by category and check index.
+    pub checks: BTreeMap<(Category, usize), (usize, usize, f32)>,
+    /// Times at or above the threshold and times computed.
+    pub scores: BTreeMap<Category, (usize, usize)>,
+    /// Occurrences by detection code.
+    pub detections: BTreeMap<u16, usize>,
+}
+
+impl Summary {
+    pub fn new<'a>(events: impl IntoIterator<Item = &'a Event>) -> Summary {
+        let mut summary = Summary::default();
+        for event in events {
+            match event.kind {
+                EventKind::Start => summary.runs += 1,
+                EventKind::Check { category, check, weight, fired } => {
+                    let entry = summary.checks.entry((category, check)).or_insert((0, 0, weight));
+                    entry.0 += fired as usize;
+                    entry.1 += 1;
+                }
+                EventKind::Score { category, score, threshold } => {
+                    let entry = summary.scores.entry(category).or_default();
+                    entry.0 += (score >= threshold) as usize;
+                    entry.1 += 1;
+                }
+                EventKind::Detection { code } => *summary.detections.entry(code).or_default() += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} run(s)", self.runs)?;
+        writeln!(f, "\nDetections (each would have triggered the reaction):")?;
+        if self.detections.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for (code, count) in &self.detections {
+            writeln!(f, "  E{} {:<24} {:>6}", code, detection_name(*code).unwrap_or("other failure"), count)?;
+        }
+        writeln!(f, "\nScores at or above the threshold:")?;
+        for (category, (over, total)) in &self.scores {
+            writeln!(f, "  {:<29} {:>6} / {}", category.to_string(), over, total)?;
+        }
+        writeln!(f, "\nChecks fired:")?;
+        for ((category, check), (fired, total, weight)) in &self.checks {
+            let name = format!("{} {}", category, check_name(*category, *check).unwrap_or("?"));
+            writeln!(f, "  {:<29} {:>6} / {:<6} weight {}", name, fired, total, weight)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("syslog".parse(), Ok(AuditTarget::Syslog(DEFAULT_SYSLOG_SOCKET.into())));
+        assert_eq!("syslog:/run/log".parse(), Ok(AuditTarget::Syslog("/run/log".into())));
+        assert_eq!("file:/var/log/rpack.jsonl".parse(), Ok(AuditTarget::File("/var/log/rpack.jsonl".into())));
+        for target in [AuditTarget::File("a:b".into()), AuditTarget::Syslog("/dev/log".into())] {
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+        assert!("file:".parse::<AuditTarget>().is_err());
+        assert!("stderr".parse::<AuditTarget>().is_err());
+    }
+
+    #[test]
+    fn test_events_are_json_lines() {
+        let events = [
+            EventKind::Start,
+            EventKind::Check { category: Category::Vm, check: 9, weight: 1.0, fired: true },
+            EventKind::Score { category: Category::Debugger, score: 0.5, threshold: 1.5 },
+            EventKind::Detection { code: 104 },
+        ];
+        for kind in events {
+            let event = Event { time: 1_700_000_000, pid: 42, kind };
+            let line = event.to_string();
+            assert!(!line.contains('\n'));
+            assert_eq!(serde_json::from_str::<Event>(&line).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let run = |pid, fired| {
+            [
+                EventKind::Start,
+                EventKind::Check { category: Category::Vm, check: 0, weight: 2.0, fired },
+                EventKind::Check { category: Category::Debugger, check: 5, weight: 2.0, fired: false },
+                EventKind::Score { category: Category::Vm, score: if fired { 2.0 } else { 0.0 }, threshold: 1.5 },
+            ]
+            .into_iter()
+            .chain(fired.then_some(EventKind::Detection { code: 104 }))
+            .map(move |kind| Event { time: 0, pid, kind })
+        };
+        let events: Vec<Event> = run(1, true).chain(run(2, false)).chain(run(3, true)).collect();
+        let summary = Summary::new(&events);
+        assert_eq!(summary.runs, 3);
+        assert_eq!(summary.checks[&(Category::Vm, 0)], (2, 3, 2.0));
+        assert_eq!(summary.checks[&(Category::Debugger, 5)], (0, 3, 2.0));
+        assert_eq!(summary.scores[&Category::Vm], (2, 3));
+        assert_eq!(summary.detections[&104], 2);
+
+        let report = summary.to_string();
+        assert!(report.contains("E104 VM"));
+        assert!(report.contains("VM hypervisor flag"));
+        assert!(report.contains("debugger SIGTRAP handler"));
+    }
+}
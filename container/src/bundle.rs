@@ -0,0 +1,170 @@
+//! Multi-call bundles, packed with `rpack --program`: further executables in the
+//! `programs` section, run instead of the main payload when the packed binary is invoked
+//! under their name, busybox-style.
+//!
+//! Section layout: `[index size] [index] [encrypted programs]`, the size a little-endian
+//! `u64` and the index a bincode `Vec<Program>`. Each program is encrypted like the main
+//! payload (`[counter block] [AES-128-CTR ciphertext]`) with a key of its own, stored
+//! wrapped by the payload key, so the stub decrypts only the program it runs.
+
+use crate::trailer::{Section, BLAKE3_SIZE, MAX_DECOMPRESSED_SIZE, MAX_PAYLOAD_SIZE, NONCE_SIZE};
+use checksum::compute_blake3;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// Far more than a dozen tools
+const MAX_INDEX_SIZE: u64 = 1 << 20;
+// LZ4 cannot expand its input more than ~255 times
+const MAX_LZ4_RATIO: u64 = 256;
+
+/// Index entry of a bundled program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Program {
+    pub name: String,
+    /// Key of this program, XORed with [`key_mask`].
+    pub wrapped_key: [u8; 16],
+    /// Encrypted program, relative to the end of the index.
+    pub offset: u64,
+    pub len: u64,
+    pub decompressed_size: u64,
+    pub original_hash: [u8; BLAKE3_SIZE],
+    pub compressed_hash: [u8; BLAKE3_SIZE],
+}
+
+impl Program {
+    /// Key of this program, given the payload key.
+    pub fn key(&self, payload_key: &[u8; 16]) -> [u8; 16] {
+        let mask = key_mask(payload_key, &self.name);
+        std::array::from_fn(|i| self.wrapped_key[i] ^ mask[i])
+    }
+}
+
+/// XOR mask wrapping the key of program `name`: only the payload key unwraps it.
+pub fn key_mask(payload_key: &[u8; 16], name: &str) -> [u8; 16] {
+    let mut input = b"rpack program key v1".to_vec();
+    input.extend_from_slice(payload_key);
+    input.extend_from_slice(name.as_bytes());
+    let mut mask = [0u8; 16];
+    mask.copy_from_slice(&compute_blake3(&input)[..16]);
+    mask
+}
+
+/// Whether `name` can name a program: a file name, since symlinks are how programs
+/// are usually selected.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+/// `[index size] [index] [programs]` for `programs`, whose offsets index the
+/// concatenation of `encrypted`.
+pub fn encode(programs: &[Program], encrypted: &[Vec<u8>]) -> Vec<u8> {
+    let index = bincode::serialize(programs).expect("Failed to serialize the program index");
+    let mut section = (index.len() as u64).to_le_bytes().to_vec();
+    section.extend_from_slice(&index);
+    for program in encrypted {
+        section.extend_from_slice(program);
+    }
+    section
+}
+
+/// Index of the `programs` section and the bytes its offsets refer to, or None if the
+/// section is malformed. Like [`crate::trailer::Trailer::parse`], every size is checked
+/// before anything is allocated.
+pub fn parse(section: &[u8]) -> Option<(Vec<Program>, &[u8])> {
+    let (size, rest) = section.split_first_chunk::<8>()?;
+    let size = u64::from_le_bytes(*size);
+    if size > MAX_INDEX_SIZE {
+        return None;
+    }
+    let (index, programs) = rest.split_at_checked(size as usize)?;
+    let index: Vec<Program> = crate::decode(index)?;
+    for (i, program) in index.iter().enumerate() {
+        let compressed_size = program.len.checked_sub(NONCE_SIZE)?;
+        let fits = Section { offset: program.offset, len: program.len }.slice(programs).is_some();
+        if !fits
+            || program.len > MAX_PAYLOAD_SIZE
+            || program.decompressed_size > compressed_size.saturating_mul(MAX_LZ4_RATIO).min(MAX_DECOMPRESSED_SIZE)
+            || !valid_name(&program.name)
+            || index[..i].iter().any(|other| other.name == program.name)
+        {
+            return None;
+        }
+    }
+    Some((index, programs))
+}
+
+/// Program to run for this command line, and whether it was named by the first argument
+/// (which then replaces argv[0]) rather than by argv[0]. None runs the main payload.
+pub fn select(programs: &[Program], argv0: Option<&OsStr>, argv1: Option<&OsStr>) -> Option<(usize, bool)> {
+    let find = |name: &OsStr| programs.iter().position(|program| program.name.as_bytes() == name.as_bytes());
+    let invoked_as = argv0.and_then(|argv0| Path::new(argv0).file_name());
+    if let Some(index) = invoked_as.and_then(find) {
+        return Some((index, false));
+    }
+    argv1.and_then(find).map(|index| (index, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(name: &str, offset: u64, len: u64) -> Program {
+        Program {
+            name: name.into(),
+            wrapped_key: [1; 16],
+            offset,
+            len,
+            decompressed_size: 100,
+            original_hash: [2; BLAKE3_SIZE],
+            compressed_hash: [3; BLAKE3_SIZE],
+        }
+    }
+
+    #[test]
+    fn test_encode_and_parse() {
+        let programs = vec![program("ls", 0, 40), program("cat", 40, 30)];
+        let section = encode(&programs, &[vec![4; 40], vec![5; 30]]);
+        let (index, encrypted) = parse(&section).unwrap();
+        assert_eq!(index, programs);
+        assert_eq!(encrypted.len(), 70);
+        assert_eq!(Section { offset: index[1].offset, len: index[1].len }.slice(encrypted), Some(&[5; 30][..]));
+
+        assert_eq!(parse(&section[..section.len() - 1]), None);
+        assert_eq!(parse(&encode(&[program("ls", 0, 40), program("ls", 40, 30)], &[vec![0; 70]])), None);
+        assert_eq!(parse(&encode(&[program("a/b", 0, 40)], &[vec![0; 40]])), None);
+        // Too small for its counter block, or for its decompressed size
+        assert_eq!(parse(&encode(&[program("ls", 0, 8)], &[vec![0; 8]])), None);
+        assert_eq!(parse(&encode(&[Program { decompressed_size: u64::MAX, ..program("ls", 0, 40) }], &[vec![0; 40]])), None);
+        // Possible for LZ4, but more than the stub unpacks
+        let large = Program { decompressed_size: MAX_DECOMPRESSED_SIZE + 1, ..program("ls", 0, 1 << 23) };
+        assert_eq!(parse(&encode(&[large], &[vec![0; 1 << 23]])), None);
+        let mut forged = section.clone();
+        forged[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&forged), None);
+    }
+
+    #[test]
+    fn test_select() {
+        let programs = vec![program("ls", 0, 40), program("cat", 40, 30)];
+        let select = |argv0: &str, argv1: Option<&str>| select(&programs, Some(OsStr::new(argv0)), argv1.map(OsStr::new));
+        assert_eq!(select("/usr/local/bin/cat", Some("ls")), Some((1, false)));
+        assert_eq!(select("./tools", Some("ls")), Some((0, true)));
+        assert_eq!(select("./tools", Some("-l")), None);
+        assert_eq!(select("./tools", None), None);
+        assert_eq!(select("ls/", None), Some((0, false)));
+    }
+
+    #[test]
+    fn test_program_key() {
+        let payload_key = [7; 16];
+        let key = [9; 16];
+        let mask = key_mask(&payload_key, "ls");
+        let wrapped = Program { wrapped_key: std::array::from_fn(|i| key[i] ^ mask[i]), ..program("ls", 0, 40) };
+        assert_eq!(wrapped.key(&payload_key), key);
+        assert_ne!(wrapped.key(&[8; 16]), key);
+        // The mask depends on the name, so keys cannot be swapped between programs
+        assert_ne!(key_mask(&payload_key, "cat"), mask);
+    }
+}
@@ -0,0 +1,480 @@
+//! Where the pack data lives in a packed binary.
+//!
+//! The packer appends a read-only `PT_LOAD` segment to the stub (see [`embed`]) holding a
+//! single ELF note named `rpack`, whose descriptor is the pack data: `[encrypted_payload]
+//! ... [signature]` as described in [`crate::trailer`]. An `SHT_NOTE` section named
+//! `.rpack` covers the segment, so the kernel maps it with the stub and tools that rewrite
+//! files by section (`strip`, `objcopy`) keep it.
+//!
+//! There is no room to add a program header to a linked binary: GNU binutils always put
+//! the table right after the ELF header, where the stub's first sections already are. The
+//! stub therefore reserves one, the `PT_NOTE` of an empty `rpack` note in a section of its
+//! own ([`reserved_note`]), which the packer turns into the `PT_LOAD` of the pack data.
+//!
+//! The stub finds the note through its own program headers in memory, the packer and
+//! `rpack verify` through the ones in the file: [`find_pack`] works on either view.
+
+use blake3::Hasher;
+use std::fmt;
+use std::ops::Range;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+pub const PT_PHDR: u32 = 6;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+pub const SHT_NOTE: u32 = 7;
+pub const SHF_ALLOC: u64 = 2;
+
+pub const ELF_HEADER_SIZE: usize = 64;
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+pub const SECTION_HEADER_SIZE: usize = 64;
+
+/// Name and type of the note holding the pack data.
+pub const PACK_NOTE_NAME: &[u8] = b"rpack\0";
+pub const PACK_NOTE_TYPE: u32 = 0x5250_4b01;
+pub const PACK_SECTION_NAME: &[u8] = b".rpack\0";
+/// Largest pack data the note can hold: its descriptor size is 32 bits.
+pub const MAX_PACK_DATA_SIZE: u64 = u32::MAX as u64;
+
+const PAGE_SIZE: u64 = 0x1000;
+const NOTE_ALIGN: usize = 4;
+const NOTE_HEADER_SIZE: usize = 12;
+// PACK_NOTE_NAME, padded to NOTE_ALIGN
+const PADDED_NAME_SIZE: usize = 8;
+pub const RESERVED_NOTE_SIZE: usize = NOTE_HEADER_SIZE + PADDED_NAME_SIZE;
+const SIGNATURE_SIZE: usize = checksum::signature::SIGNATURE_SIZE;
+
+/// The `rpack` note with an empty descriptor that the stub puts in a section of its own,
+/// aligned differently from its other notes, to reserve a program header.
+pub const fn reserved_note() -> [u8; RESERVED_NOTE_SIZE] {
+    let mut note = [0u8; RESERVED_NOTE_SIZE];
+    note[0] = PACK_NOTE_NAME.len() as u8;
+    let kind = PACK_NOTE_TYPE.to_le_bytes();
+    let mut i = 0;
+    while i < 4 {
+        note[8 + i] = kind[i];
+        i += 1;
+    }
+    let mut i = 0;
+    while i < PACK_NOTE_NAME.len() {
+        note[NOTE_HEADER_SIZE + i] = PACK_NOTE_NAME[i];
+        i += 1;
+    }
+    note
+}
+
+/// Why the packer could not rewrite the stub.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Not a little-endian ELF64 executable, or its headers do not fit in the file.
+    Malformed,
+    /// The stub has no `PT_PHDR`, which the stub needs to find its headers in memory.
+    NoPhdrSegment,
+    /// The stub has no [`reserved_note`] with a program header of its own.
+    NoReservedHeader,
+    /// The pack data does not fit in a note.
+    TooLarge(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::Malformed => write!(f, "stub is not a valid ELF64 little-endian executable"),
+            ElfError::NoPhdrSegment => write!(f, "stub has no PT_PHDR program header"),
+            ElfError::NoReservedHeader => write!(f, "stub has no program header reserved for the pack data"),
+            ElfError::TooLarge(size) => write!(f, "pack data too large for an ELF note ({} bytes, at most {})", size, MAX_PACK_DATA_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+/// An ELF64 program header. `p_paddr` is always equal to `p_vaddr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> ProgramHeader {
+        ProgramHeader {
+            kind: u32_at(bytes, 0),
+            flags: u32_at(bytes, 4),
+            offset: u64_at(bytes, 8),
+            vaddr: u64_at(bytes, 16),
+            filesz: u64_at(bytes, 32),
+            memsz: u64_at(bytes, 40),
+            align: u64_at(bytes, 48),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; PROGRAM_HEADER_SIZE] {
+        let mut bytes = [0u8; PROGRAM_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&self.kind.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.flags.to_le_bytes());
+        for (i, field) in [self.offset, self.vaddr, self.vaddr, self.filesz, self.memsz, self.align].iter().enumerate() {
+            bytes[8 + 8 * i..16 + 8 * i].copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Parse a program header table, such as the one at `AT_PHDR` in memory.
+pub fn parse_program_headers(table: &[u8]) -> Vec<ProgramHeader> {
+    table.chunks_exact(PROGRAM_HEADER_SIZE).map(ProgramHeader::parse).collect()
+}
+
+// The parts of the ELF header the packer rewrites
+struct ElfHeader {
+    phoff: usize,
+    phnum: usize,
+    shoff: usize,
+    shnum: usize,
+    shstrndx: usize,
+}
+
+fn parse_elf_header(image: &[u8]) -> Option<ElfHeader> {
+    // ELFCLASS64, ELFDATA2LSB
+    if image.len() < ELF_HEADER_SIZE || image[..4] != *b"\x7FELF" || image[4] != 2 || image[5] != 1 {
+        return None;
+    }
+    if usize::from(u16_at(image, 54)) != PROGRAM_HEADER_SIZE {
+        return None;
+    }
+    let header = ElfHeader {
+        phoff: usize::try_from(u64_at(image, 32)).ok()?,
+        phnum: usize::from(u16_at(image, 56)),
+        shoff: usize::try_from(u64_at(image, 40)).ok()?,
+        shnum: usize::from(u16_at(image, 60)),
+        shstrndx: usize::from(u16_at(image, 62)),
+    };
+    let phend = header.phoff.checked_add(header.phnum * PROGRAM_HEADER_SIZE)?;
+    let shend = header.shoff.checked_add(header.shnum * SECTION_HEADER_SIZE)?;
+    if phend > image.len() || shend > image.len() || (header.shnum > 0 && header.shstrndx >= header.shnum) {
+        return None;
+    }
+    Some(header)
+}
+
+/// The program headers of an ELF file.
+pub fn file_program_headers(image: &[u8]) -> Option<Vec<ProgramHeader>> {
+    let header = parse_elf_header(image)?;
+    Some(parse_program_headers(&image[header.phoff..header.phoff + header.phnum * PROGRAM_HEADER_SIZE]))
+}
+
+/// Pack data found in a packed binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pack<'a> {
+    /// Descriptor of the `rpack` note.
+    pub data: &'a [u8],
+    /// What the signature at the end of `data` signs (see [`find_pack`]).
+    pub digest: [u8; 32],
+    /// The `PT_LOAD` segment holding the note.
+    pub segment: ProgramHeader,
+}
+
+/// Find the pack data through `headers`.
+///
+/// `segment` returns the `p_filesz` bytes of a `PT_LOAD` segment, from the file (at
+/// `p_offset`) or from memory (at `p_vaddr` plus the load bias). The pack segment is the
+/// read-only one that starts with the `rpack` note; there must be exactly one.
+///
+/// The digest is the BLAKE3 hash of every other read-only `PT_LOAD` segment, without the
+/// section header fields of the ELF header (`e_shoff`, `e_shnum` and `e_shstrndx`) and the
+/// `p_offset` fields of the program header table, which tools rewrite, followed by the pack
+/// data without its signature. It covers the stub code and constants, including the
+/// embedded public key, as they are both in the file and once mapped. A segment whose
+/// skipped fields do not fit in it makes the digest None rather than leaving it out.
+pub fn find_pack<'a>(headers: &[ProgramHeader], segment: impl Fn(&ProgramHeader) -> Option<&'a [u8]>) -> Option<Pack<'a>> {
+    let read_only = || headers.iter().filter(|header| header.kind == PT_LOAD && header.flags & PF_W == 0);
+    let mut found = None;
+    for load in read_only() {
+        if let Some(data) = pack_note(segment(load)?) {
+            if found.is_some() {
+                return None;
+            }
+            found = Some((*load, data));
+        }
+    }
+    let (pack_segment, data) = found?;
+
+    let table = headers.iter().find(|header| header.kind == PT_PHDR);
+    let mut hasher = Hasher::new();
+    for load in read_only().filter(|load| **load != pack_segment) {
+        let bytes = segment(load)?;
+        let mut skipped = Vec::new();
+        // The ELF header fields that strip and objcopy rewrite, if the header is in this segment
+        for field in [40..48usize, 60..64] {
+            if let Some(start) = usize::try_from(load.offset).ok().and_then(|offset| field.start.checked_sub(offset)) {
+                skipped.push(start..start + field.len());
+            }
+        }
+        // Where each segment sits in the file changes when sections are removed or added
+        let table_start = table.and_then(|table| usize::try_from(table.vaddr.checked_sub(load.vaddr)?).ok());
+        if let Some(start) = table_start.filter(|start| start.checked_add(headers.len() * PROGRAM_HEADER_SIZE).is_some_and(|end| end <= bytes.len())) {
+            skipped.extend((0..headers.len()).map(|index| {
+                let field = start + index * PROGRAM_HEADER_SIZE + 8;
+                field..field + 8
+            }));
+        }
+        skipped.sort_by_key(|range| range.start);
+        let mut from = 0;
+        for range in skipped {
+            hasher.update(bytes.get(from..range.start.max(from))?);
+            from = from.max(range.end);
+        }
+        hasher.update(bytes.get(from..)?);
+    }
+    hasher.update(&data[..data.len().checked_sub(SIGNATURE_SIZE)?]);
+    Some(Pack { data, digest: *hasher.finalize().as_bytes(), segment: pack_segment })
+}
+
+/// Reads the segments of an ELF file, for [`find_pack`] and [`text_hashes`].
+pub fn file_segment<'a>(image: &'a [u8]) -> impl Fn(&ProgramHeader) -> Option<&'a [u8]> {
+    move |header| {
+        let start = usize::try_from(header.offset).ok()?;
+        image.get(start..start.checked_add(usize::try_from(header.filesz).ok()?)?)
+    }
+}
+
+/// [`find_pack`] on the program headers and segments of an ELF file.
+pub fn find_pack_in_file(image: &[u8]) -> Option<Pack<'_>> {
+    find_pack(&file_program_headers(image)?, file_segment(image))
+}
+
+/// BLAKE3 hash of each executable `PT_LOAD` segment, in program header order, with
+/// `segment` reading them as in [`find_pack`].
+pub fn text_hashes<'a>(headers: &[ProgramHeader], segment: impl Fn(&ProgramHeader) -> Option<&'a [u8]>) -> Option<Vec<[u8; 32]>> {
+    headers
+        .iter()
+        .filter(|header| header.kind == PT_LOAD && header.flags & PF_X != 0)
+        .map(|header| segment(header).map(|bytes| *blake3::hash(bytes).as_bytes()))
+        .collect()
+}
+
+// Descriptor of the rpack note at the start of `bytes`
+fn pack_note(bytes: &[u8]) -> Option<&[u8]> {
+    let header = bytes.get(..RESERVED_NOTE_SIZE)?;
+    let desc_size = u32_at(header, 4) as usize;
+    if u32_at(header, 0) as usize != PACK_NOTE_NAME.len()
+        || u32_at(header, 8) != PACK_NOTE_TYPE
+        || header[NOTE_HEADER_SIZE..NOTE_HEADER_SIZE + PACK_NOTE_NAME.len()] != *PACK_NOTE_NAME
+    {
+        return None;
+    }
+    bytes.get(RESERVED_NOTE_SIZE..RESERVED_NOTE_SIZE.checked_add(desc_size)?)
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+/// Append `data` to `stub` in a new read-only segment, as an `rpack` note.
+///
+/// The program header of the stub's [`reserved_note`] becomes the `PT_LOAD` of the new
+/// segment, placed past both the end of the file and the end of the stub in memory.
+/// Returns the packed image and where `data` is in it.
+pub fn embed(stub: &[u8], data: &[u8]) -> Result<(Vec<u8>, Range<usize>), ElfError> {
+    let header = parse_elf_header(stub).ok_or(ElfError::Malformed)?;
+    let mut headers = file_program_headers(stub).ok_or(ElfError::Malformed)?;
+    if !headers.iter().any(|header| header.kind == PT_PHDR) {
+        return Err(ElfError::NoPhdrSegment);
+    }
+    if data.len() as u64 > MAX_PACK_DATA_SIZE {
+        return Err(ElfError::TooLarge(data.len()));
+    }
+    let desc_size = data.len() as u32;
+    let reserved = headers
+        .iter()
+        .position(|header| {
+            let content = usize::try_from(header.offset).ok().and_then(|offset| stub.get(offset..offset.checked_add(RESERVED_NOTE_SIZE)?));
+            header.kind == PT_NOTE && header.filesz >= RESERVED_NOTE_SIZE as u64 && content == Some(&reserved_note()[..])
+        })
+        .ok_or(ElfError::NoReservedHeader)?;
+
+    let memory_end = headers
+        .iter()
+        .filter(|header| header.kind == PT_LOAD)
+        .map(|header| header.vaddr.saturating_add(header.memsz))
+        .max()
+        .ok_or(ElfError::Malformed)?;
+    let offset = align_up(stub.len() as u64, PAGE_SIZE);
+    let note_size = RESERVED_NOTE_SIZE + align_up(data.len() as u64, NOTE_ALIGN as u64) as usize;
+    let load = ProgramHeader {
+        kind: PT_LOAD,
+        flags: PF_R,
+        offset,
+        vaddr: align_up(memory_end, PAGE_SIZE),
+        filesz: note_size as u64,
+        memsz: note_size as u64,
+        align: PAGE_SIZE,
+    };
+    // PT_LOAD entries must stay sorted by address
+    headers.remove(reserved);
+    let last_load = headers.iter().rposition(|header| header.kind == PT_LOAD).ok_or(ElfError::Malformed)?;
+    headers.insert(last_load + 1, load);
+
+    let mut image = stub.to_vec();
+    for (index, program_header) in headers.iter().enumerate() {
+        let at = header.phoff + index * PROGRAM_HEADER_SIZE;
+        image[at..at + PROGRAM_HEADER_SIZE].copy_from_slice(&program_header.to_bytes());
+    }
+    image.resize(offset as usize, 0);
+    image.extend_from_slice(&reserved_note());
+    image[offset as usize + 4..offset as usize + 8].copy_from_slice(&desc_size.to_le_bytes());
+    let data_start = image.len();
+    image.extend_from_slice(data);
+    image.resize(offset as usize + note_size, 0);
+
+    if header.shnum > 0 {
+        append_section(&mut image, stub, &header, &load);
+    }
+    Ok((image, data_start..data_start + data.len()))
+}
+
+// Append a copy of the section names and headers with one more section for the note
+fn append_section(image: &mut Vec<u8>, stub: &[u8], header: &ElfHeader, note: &ProgramHeader) {
+    let section_header = |index: usize| &stub[header.shoff + index * SECTION_HEADER_SIZE..][..SECTION_HEADER_SIZE];
+    let names = section_header(header.shstrndx);
+    let names_offset = u64_at(names, 24) as usize;
+    let names_size = u64_at(names, 32) as usize;
+    let old_names = stub.get(names_offset..names_offset.saturating_add(names_size)).unwrap_or_default();
+
+    let new_names_offset = image.len();
+    image.extend_from_slice(old_names);
+    image.extend_from_slice(PACK_SECTION_NAME);
+    let new_names_size = image.len() - new_names_offset;
+    image.resize(align_up(image.len() as u64, 8) as usize, 0);
+
+    let shoff = image.len();
+    for index in 0..header.shnum {
+        let mut section = section_header(index).to_vec();
+        if index == header.shstrndx {
+            section[24..32].copy_from_slice(&(new_names_offset as u64).to_le_bytes());
+            section[32..40].copy_from_slice(&(new_names_size as u64).to_le_bytes());
+        }
+        image.extend_from_slice(&section);
+    }
+    let mut section = [0u8; SECTION_HEADER_SIZE];
+    section[0..4].copy_from_slice(&(old_names.len() as u32).to_le_bytes());
+    section[4..8].copy_from_slice(&SHT_NOTE.to_le_bytes());
+    section[8..16].copy_from_slice(&SHF_ALLOC.to_le_bytes());
+    for (at, field) in [(16, note.vaddr), (24, note.offset), (32, note.filesz), (48, NOTE_ALIGN as u64)] {
+        section[at..at + 8].copy_from_slice(&field.to_le_bytes());
+    }
+    image.extend_from_slice(&section);
+
+    image[40..48].copy_from_slice(&(shoff as u64).to_le_bytes());
+    image[60..62].copy_from_slice(&((header.shnum + 1) as u16).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal PIE: PT_PHDR, one PT_LOAD and the PT_NOTE of the reserved note, no sections
+    fn stub() -> Vec<u8> {
+        let mut image = vec![0u8; 0x400];
+        image[..8].copy_from_slice(b"\x7FELF\x02\x01\x01\x00");
+        image[32..40].copy_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+        image[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        image[56..58].copy_from_slice(&3u16.to_le_bytes());
+        let table = 3 * PROGRAM_HEADER_SIZE as u64;
+        let phdr = ProgramHeader { kind: PT_PHDR, flags: PF_R, offset: 64, vaddr: 64, filesz: table, memsz: table, align: 8 };
+        let load = ProgramHeader { kind: PT_LOAD, flags: PF_R | PF_X, offset: 0, vaddr: 0, filesz: 0x400, memsz: 0x1800, align: PAGE_SIZE };
+        let note = ProgramHeader { kind: PT_NOTE, flags: PF_R, offset: 0x300, vaddr: 0x300, filesz: RESERVED_NOTE_SIZE as u64, memsz: RESERVED_NOTE_SIZE as u64, align: 8 };
+        for (index, header) in [phdr, note, load].iter().enumerate() {
+            let at = ELF_HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+            image[at..at + PROGRAM_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        }
+        image[0x300..0x300 + RESERVED_NOTE_SIZE].copy_from_slice(&reserved_note());
+        image[0x200] = 0xAA;
+        image
+    }
+
+    #[test]
+    fn test_embed_and_find() {
+        let data: Vec<u8> = (0..200u8).collect();
+        let (image, range) = embed(&stub(), &data).unwrap();
+        assert_eq!(&image[range], &data[..]);
+
+        // The reserved PT_NOTE became a PT_LOAD past the stub, after the other PT_LOAD
+        let headers = file_program_headers(&image).unwrap();
+        assert_eq!(headers.iter().map(|header| header.kind).collect::<Vec<_>>(), [PT_PHDR, PT_LOAD, PT_LOAD]);
+        assert_eq!((headers[2].offset, headers[2].vaddr, headers[2].flags), (0x1000, 0x2000, PF_R));
+
+        let pack = find_pack_in_file(&image).unwrap();
+        assert_eq!(pack.data, &data[..]);
+        assert_eq!(pack.segment, headers[2]);
+
+        // The digest covers the stub segments but not the section header fields of the ELF
+        // header, nor the file offsets in the program header table
+        let mut changed = image.clone();
+        changed[40] ^= 1;
+        changed[60] ^= 1;
+        changed[ELF_HEADER_SIZE + 8] ^= 1;
+        assert_eq!(find_pack_in_file(&changed).unwrap().digest, pack.digest);
+        changed[24] ^= 1;
+        assert_ne!(find_pack_in_file(&changed).unwrap().digest, pack.digest);
+        changed[24] ^= 1;
+        changed[ELF_HEADER_SIZE + 4] ^= 1;
+        assert_ne!(find_pack_in_file(&changed).unwrap().digest, pack.digest);
+        changed[ELF_HEADER_SIZE + 4] ^= 1;
+        changed[0x200] ^= 1;
+        assert_ne!(find_pack_in_file(&changed).unwrap().digest, pack.digest);
+
+        // Only the stub segment is executable
+        let hashes = text_hashes(&headers, file_segment(&image)).unwrap();
+        assert_eq!(hashes, [*blake3::hash(&image[..0x400]).as_bytes()]);
+        assert_ne!(text_hashes(&headers, file_segment(&changed)), Some(hashes));
+    }
+
+    #[test]
+    fn test_digest_with_phdr_outside_segment() {
+        let (image, _) = embed(&stub(), &[1; 100]).unwrap();
+        // PT_PHDR is the first program header, its p_vaddr is 16 bytes in
+        let mut moved = image.clone();
+        moved[ELF_HEADER_SIZE + 16..ELF_HEADER_SIZE + 24].copy_from_slice(&0x1000_0000u64.to_le_bytes());
+        let digest = find_pack_in_file(&moved).map(|pack| pack.digest);
+        moved[0x200] ^= 1;
+        assert_ne!(find_pack_in_file(&moved).map(|pack| pack.digest), digest);
+        assert_ne!(digest, Some(find_pack_in_file(&image).unwrap().digest));
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert_eq!(embed(b"MZ", b"data"), Err(ElfError::Malformed));
+        let mut no_phdr = stub();
+        no_phdr[64..68].copy_from_slice(&PT_LOAD.to_le_bytes());
+        assert_eq!(embed(&no_phdr, b"data"), Err(ElfError::NoPhdrSegment));
+        let mut no_reserved = stub();
+        no_reserved[0x300] = 0;
+        assert_eq!(embed(&no_reserved, b"data"), Err(ElfError::NoReservedHeader));
+
+        // No pack note, or one that does not fit in its segment
+        assert_eq!(find_pack_in_file(&stub()), None);
+        let (mut image, _) = embed(&stub(), &[0; 100]).unwrap();
+        image[0x1004..0x1008].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(find_pack_in_file(&image), None);
+    }
+}
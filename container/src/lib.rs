@@ -0,0 +1,244 @@
+use bincode::Options;
+use checksum::signature::PUBLIC_KEY_SIZE;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub mod audit;
+pub mod bundle;
+pub mod elf;
+pub mod license;
+pub mod passphrase;
+pub mod resources;
+pub mod script;
+pub mod trailer;
+
+/// Marks the slot of the stub where the packer writes the signing public key.
+pub const PUBLIC_KEY_MARKER: [u8; 16] = *b"rpack-pubkey-v1\0";
+pub const PUBLIC_KEY_SLOT_SIZE: usize = PUBLIC_KEY_MARKER.len() + PUBLIC_KEY_SIZE;
+
+/// Initial content of the public key slot: the marker followed by an all-zero key.
+pub const fn empty_public_key_slot() -> [u8; PUBLIC_KEY_SLOT_SIZE] {
+    let mut slot = [0u8; PUBLIC_KEY_SLOT_SIZE];
+    let mut i = 0;
+    while i < PUBLIC_KEY_MARKER.len() {
+        slot[i] = PUBLIC_KEY_MARKER[i];
+        i += 1;
+    }
+    slot
+}
+
+/// Offset of the public key in a stub image.
+///
+/// Returns None unless the marker appears exactly once, so the packer never patches
+/// the wrong bytes.
+pub fn find_public_key(stub: &[u8]) -> Option<usize> {
+    let mut found = stub
+        .windows(PUBLIC_KEY_MARKER.len())
+        .enumerate()
+        .filter(|(_, window)| *window == PUBLIC_KEY_MARKER)
+        .map(|(offset, _)| offset + PUBLIC_KEY_MARKER.len());
+    let offset = found.next()?;
+    if found.next().is_some() || offset + PUBLIC_KEY_SIZE > stub.len() {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Deserialize a section written by the packer with `bincode::serialize`.
+///
+/// Unlike `bincode::deserialize`, reads are limited to `bytes.len()` so a forged length
+/// prefix cannot trigger a huge allocation, and trailing bytes are rejected.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+        .ok()
+}
+
+/// Stub settings chosen at pack time and stored in the packed binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    /// Run the anti-VM checks before unpacking.
+    pub anti_vm: bool,
+    /// Refuse to run in Docker, Podman or LXC containers.
+    pub anti_container: bool,
+    /// Refuse to run under CPU emulation (QEMU's TCG, qemu-user, unicorn).
+    pub anti_emulator: bool,
+    /// Run the weighted debugger checks (tracer, `PTRACE_TRACEME`, `LD_PRELOAD`, parent
+    /// process, debug registers, SIGTRAP) before unpacking.
+    pub anti_debugger: bool,
+    /// Environments, such as build farm VMs, in which the VM, container and emulator
+    /// checks are skipped.
+    pub trusted_environments: Vec<fingerprint::environment::TrustedEnvironment>,
+    /// Keep running when /proc is not mounted (chroots, minimal containers) instead
+    /// of treating it as tampering. The tracer checks are skipped in that case.
+    pub allow_missing_proc: bool,
+    /// What to do when tampering, a debugger or a VM is detected.
+    pub reaction: Reaction,
+    /// Payload key of a machine-bound binary, masked once per allowed host with
+    /// `fingerprint::host_key_mask`. Empty when the binary runs anywhere.
+    pub host_keys: Vec<[u8; 16]>,
+    /// License checked before the payload key is unwrapped.
+    pub license: Option<license::SignedLicense>,
+    /// Passphrase whose share is XORed into the key recovered from the whitebox,
+    /// before any host binding is unwrapped.
+    pub passphrase: Option<passphrase::Passphrase>,
+    /// Run the payload in a child that the stub traces, so no debugger can attach to it.
+    pub guardian: bool,
+    /// BLAKE3 hashes of the stub's executable segments (see [`elf::text_hashes`]),
+    /// compared with the segments in memory while unpacking.
+    pub text_hashes: Vec<[u8; 32]>,
+    /// Record every check to this target and keep running, instead of reacting to
+    /// detections (see [`audit`]).
+    pub audit: Option<audit::AuditTarget>,
+    /// Set when the payload is a script, to run with this interpreter.
+    pub script: Option<script::Script>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            anti_vm: true,
+            anti_container: false,
+            anti_emulator: true,
+            anti_debugger: true,
+            trusted_environments: Vec::new(),
+            allow_missing_proc: false,
+            reaction: Reaction::Decoy,
+            host_keys: Vec::new(),
+            license: None,
+            passphrase: None,
+            guardian: false,
+            text_hashes: Vec::new(),
+            audit: None,
+            script: None,
+        }
+    }
+}
+
+/// Reaction of the stub to a detection.
+///
+/// Written on the command line as `exit[:CODE]`, `decoy`, `corrupt[:MAX_DELAY_MS]` or `log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reaction {
+    /// Exit with this status.
+    Exit(i32),
+    /// Run the decoy program stored in the packed binary.
+    Decoy,
+    /// Wait a random delay of up to this many milliseconds, then crash with SIGSEGV.
+    Corrupt(u32),
+    /// Warn on stderr and keep running. Failures that leave nothing to run still exit.
+    Log,
+}
+
+impl FromStr for Reaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        match (name, arg) {
+            ("exit", None) => Ok(Reaction::Exit(1)),
+            ("exit", Some(code)) => code.parse().map(Reaction::Exit).map_err(|_| format!("invalid exit code '{}'", code)),
+            ("decoy", None) => Ok(Reaction::Decoy),
+            ("corrupt", None) => Ok(Reaction::Corrupt(1000)),
+            ("corrupt", Some(delay)) => delay.parse().map(Reaction::Corrupt).map_err(|_| format!("invalid delay '{}'", delay)),
+            ("log", None) => Ok(Reaction::Log),
+            _ => Err(format!("unknown reaction '{}' (expected exit[:CODE], decoy, corrupt[:MAX_DELAY_MS] or log)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Reaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reaction::Exit(code) => write!(f, "exit:{}", code),
+            Reaction::Decoy => write!(f, "decoy"),
+            Reaction::Corrupt(delay) => write!(f, "corrupt:{}", delay),
+            Reaction::Log => write!(f, "log"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_roundtrip() {
+        let config = Config {
+            anti_vm: false,
+            anti_container: true,
+            anti_emulator: false,
+            anti_debugger: false,
+            trusted_environments: vec![fingerprint::environment::Environment {
+                hypervisor: Some("KVMKVMKVM".into()),
+                ..Default::default()
+            }
+            .trust()
+            .unwrap()],
+            allow_missing_proc: true,
+            reaction: Reaction::Exit(42),
+            host_keys: vec![[7; 16]],
+            license: None,
+            passphrase: None,
+            guardian: true,
+            text_hashes: Vec::new(),
+            audit: Some(audit::AuditTarget::Syslog(audit::DEFAULT_SYSLOG_SOCKET.into())),
+            script: Some(script::Script { interpreter: script::Interpreter::Bundled("python3".into()), arg: None }),
+        };
+        let bytes = bincode::serialize(&config).unwrap();
+        assert_eq!(decode::<Config>(&bytes), Some(config));
+        assert!(Config::default().anti_vm);
+        // Containers are a common way to deploy, emulators a common way to analyze
+        assert!(!Config::default().anti_container);
+        assert!(Config::default().anti_emulator);
+        assert!(Config::default().anti_debugger);
+        assert!(!Config::default().allow_missing_proc);
+    }
+
+    #[test]
+    fn test_parse_reaction() {
+        assert_eq!("exit".parse(), Ok(Reaction::Exit(1)));
+        assert_eq!("exit:-3".parse(), Ok(Reaction::Exit(-3)));
+        assert_eq!("decoy".parse(), Ok(Reaction::Decoy));
+        assert_eq!("corrupt:250".parse(), Ok(Reaction::Corrupt(250)));
+        assert_eq!("log".parse(), Ok(Reaction::Log));
+        for reaction in [Reaction::Exit(7), Reaction::Decoy, Reaction::Corrupt(5), Reaction::Log] {
+            assert_eq!(reaction.to_string().parse(), Ok(reaction));
+        }
+        assert!("exit:x".parse::<Reaction>().is_err());
+        assert!("decoy:1".parse::<Reaction>().is_err());
+        assert!("segfault".parse::<Reaction>().is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_forged_lengths() {
+        let bytes = bincode::serialize(&vec![1u8, 2, 3]).unwrap();
+        assert_eq!(decode::<Vec<u8>>(&bytes), Some(vec![1, 2, 3]));
+        // Length prefix claiming far more data than there is
+        let mut forged = bytes.clone();
+        forged[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(decode::<Vec<u8>>(&forged), None);
+        assert_eq!(decode::<Vec<u8>>(&[bytes, vec![0]].concat()), None);
+    }
+
+    #[test]
+    fn test_find_public_key() {
+        let mut stub = vec![0xAA; 100];
+        stub.extend_from_slice(&empty_public_key_slot());
+        stub.extend_from_slice(&[0xBB; 10]);
+        assert_eq!(find_public_key(&stub), Some(100 + PUBLIC_KEY_MARKER.len()));
+
+        // Ambiguous or truncated slots are refused
+        stub.extend_from_slice(&PUBLIC_KEY_MARKER);
+        assert_eq!(find_public_key(&stub), None);
+        assert_eq!(find_public_key(&PUBLIC_KEY_MARKER), None);
+        assert_eq!(find_public_key(&[0xAA; 100]), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! License block embedded by `rpack --not-after ...` and checked by the stub before it
+//! unwraps the payload key. The stub hands the signed block to the payload in
+//! [`LICENSE_ENV`] so it can read the claims (see the `rpack-runtime` crate).
+
+use checksum::signature::{self, PUBLIC_KEY_SIZE, SECRET_KEY_SIZE, SIGNATURE_SIZE};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Environment variable holding the hex-encoded [`SignedLicense`] of the running program.
+pub const LICENSE_ENV: &str = "RPACK_LICENSE";
+
+/// License claims.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct License {
+    pub licensee: Option<String>,
+    /// Unix time (seconds) before which the program refuses to run.
+    pub not_before: Option<u64>,
+    /// Unix time (seconds) from which the program refuses to run.
+    pub not_after: Option<u64>,
+    /// Fingerprint digests of the hosts allowed to run the program. Empty: any host.
+    pub hosts: Vec<[u8; 32]>,
+    /// Feature flags for the program to interpret.
+    pub features: Vec<String>,
+    /// Shown instead of running when the license does not allow it.
+    pub message: String,
+}
+
+/// Why a license does not allow running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LicenseError {
+    NotYetValid,
+    Expired,
+    WrongHost,
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseError::NotYetValid => write!(f, "license not valid yet"),
+            LicenseError::Expired => write!(f, "license expired"),
+            LicenseError::WrongHost => write!(f, "license not valid on this host"),
+        }
+    }
+}
+
+impl std::error::Error for LicenseError {}
+
+impl License {
+    /// Check the license at unix time `now`. `host_digest` is only called when the
+    /// license is restricted to some hosts.
+    pub fn check(&self, now: u64, host_digest: impl FnOnce() -> [u8; 32]) -> Result<(), LicenseError> {
+        if self.not_before.is_some_and(|not_before| now < not_before) {
+            return Err(LicenseError::NotYetValid);
+        }
+        if self.not_after.is_some_and(|not_after| now >= not_after) {
+            return Err(LicenseError::Expired);
+        }
+        if !self.hosts.is_empty() && !self.hosts.contains(&host_digest()) {
+            return Err(LicenseError::WrongHost);
+        }
+        Ok(())
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// [`License`] claims signed with the packer's signing key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedLicense {
+    claims: Vec<u8>,
+    pub public_key: [u8; PUBLIC_KEY_SIZE],
+    // serde only derives arrays up to 32 bytes
+    signature: Vec<u8>,
+}
+
+impl SignedLicense {
+    pub fn sign(license: &License, secret_key: &[u8; SECRET_KEY_SIZE]) -> SignedLicense {
+        let claims = bincode::serialize(license).expect("Failed to serialize license");
+        SignedLicense {
+            signature: signature::sign(secret_key, &claims).to_vec(),
+            public_key: signature::public_key(secret_key),
+            claims,
+        }
+    }
+
+    /// The claims, if they are signed with `public_key`.
+    ///
+    /// Anyone can sign a license with their own key, so always check it against the
+    /// key you expect.
+    pub fn verify(&self) -> Option<License> {
+        let signature: &[u8; SIGNATURE_SIZE] = self.signature.as_slice().try_into().ok()?;
+        if !signature::verify(&self.public_key, &self.claims, signature) {
+            return None;
+        }
+        crate::decode(&self.claims)
+    }
+
+    /// Hex encoding used for [`LICENSE_ENV`].
+    pub fn encode(&self) -> String {
+        let bytes = bincode::serialize(self).expect("Failed to serialize license");
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(hex: &str) -> Option<SignedLicense> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        crate::decode(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn license() -> License {
+        License {
+            licensee: Some("ACME".into()),
+            not_before: Some(1000),
+            not_after: Some(2000),
+            hosts: vec![[1; 32]],
+            features: vec!["export".into()],
+            message: "Evaluation expired".into(),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let license = license();
+        assert_eq!(license.check(999, || [1; 32]), Err(LicenseError::NotYetValid));
+        assert_eq!(license.check(1000, || [1; 32]), Ok(()));
+        assert_eq!(license.check(2000, || [1; 32]), Err(LicenseError::Expired));
+        assert_eq!(license.check(1500, || [2; 32]), Err(LicenseError::WrongHost));
+
+        let unrestricted = License { not_before: None, not_after: None, hosts: Vec::new(), ..license };
+        assert_eq!(unrestricted.check(u64::MAX, || unreachable!()), Ok(()));
+        assert!(unrestricted.has_feature("export"));
+        assert!(!unrestricted.has_feature("admin"));
+    }
+
+    #[test]
+    fn test_signed_roundtrip() {
+        let signed = SignedLicense::sign(&license(), &[9; 32]);
+        assert_eq!(signed.public_key, signature::public_key(&[9; 32]));
+        let decoded = SignedLicense::decode(&signed.encode()).unwrap();
+        assert_eq!(decoded.verify(), Some(license()));
+
+        let mut forged = decoded.clone();
+        forged.claims[0] ^= 1;
+        assert_eq!(forged.verify(), None);
+        assert_eq!(SignedLicense::decode("zz"), None);
+        assert_eq!(SignedLicense::decode(&signed.encode()[1..]), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Passphrase share of the payload key, embedded by `rpack --passphrase-from ...`.
+//!
+//! Argon2id turns the operator's passphrase and a stored salt into 48 bytes: a 16-byte
+//! share XORed into the key recovered from the whitebox, and a 32-byte key for a keyed
+//! check that tells a wrong passphrase apart without involving the payload key.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use checksum::{compute_keyed_blake3, validate_keyed_blake3};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+pub const SHARE_SIZE: usize = 16;
+pub const SALT_SIZE: usize = 16;
+const CHECK_KEY_SIZE: usize = 32;
+// What the check key authenticates
+const CHECK_CONTEXT: &[u8] = b"rpack passphrase check v1";
+
+/// Where the stub reads the passphrase.
+///
+/// Written on the command line as `tty`, `env:NAME` or `fd:N`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PassphraseSource {
+    /// Prompt on the controlling terminal.
+    Tty,
+    /// Read this environment variable, which is not passed on to the payload.
+    Env(String),
+    /// Read one line from this file descriptor, then close it.
+    Fd(i32),
+}
+
+impl FromStr for PassphraseSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "tty" => Ok(PassphraseSource::Tty),
+            Some(("env", name)) if !name.is_empty() && !name.contains('=') => Ok(PassphraseSource::Env(name.into())),
+            Some(("fd", fd)) => match fd.parse() {
+                Ok(fd) if fd >= 0 => Ok(PassphraseSource::Fd(fd)),
+                _ => Err(format!("invalid file descriptor '{}'", fd)),
+            },
+            _ => Err(format!("unknown passphrase source '{}' (expected tty, env:NAME or fd:N)", s)),
+        }
+    }
+}
+
+impl fmt::Display for PassphraseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassphraseSource::Tty => write!(f, "tty"),
+            PassphraseSource::Env(name) => write!(f, "env:{}", name),
+            PassphraseSource::Fd(fd) => write!(f, "fd:{}", fd),
+        }
+    }
+}
+
+/// Argon2id cost settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cost {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for Cost {
+    /// 64 MiB and 3 passes: about a quarter of a second on a current CPU.
+    fn default() -> Self {
+        Cost { memory_kib: 64 * 1024, iterations: 3, lanes: 1 }
+    }
+}
+
+/// Settings the stub needs to turn a passphrase back into its share.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passphrase {
+    pub source: PassphraseSource,
+    pub salt: [u8; SALT_SIZE],
+    pub cost: Cost,
+    check: [u8; 32],
+}
+
+impl Passphrase {
+    /// Settings for `passphrase`, and the share to mix into the key.
+    pub fn new(
+        passphrase: &[u8],
+        source: PassphraseSource,
+        salt: [u8; SALT_SIZE],
+        cost: Cost,
+    ) -> Option<(Passphrase, Zeroizing<[u8; SHARE_SIZE]>)> {
+        let (share, check_key) = derive(passphrase, &salt, cost)?;
+        let check = compute_keyed_blake3(&check_key, CHECK_CONTEXT);
+        Some((Passphrase { source, salt, cost, check }, share))
+    }
+
+    /// The share derived from `passphrase`, or None if it is not the packing passphrase.
+    pub fn share(&self, passphrase: &[u8]) -> Option<Zeroizing<[u8; SHARE_SIZE]>> {
+        let (share, check_key) = derive(passphrase, &self.salt, self.cost)?;
+        validate_keyed_blake3(&check_key, CHECK_CONTEXT, &self.check).then_some(share)
+    }
+}
+
+type Derived = (Zeroizing<[u8; SHARE_SIZE]>, Zeroizing<[u8; CHECK_KEY_SIZE]>);
+
+// None if the cost settings are out of Argon2's range
+fn derive(passphrase: &[u8], salt: &[u8; SALT_SIZE], cost: Cost) -> Option<Derived> {
+    let params = Params::new(cost.memory_kib, cost.iterations, cost.lanes, Some(SHARE_SIZE + CHECK_KEY_SIZE)).ok()?;
+    let mut output = Zeroizing::new([0u8; SHARE_SIZE + CHECK_KEY_SIZE]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut *output)
+        .ok()?;
+    let mut share = Zeroizing::new([0u8; SHARE_SIZE]);
+    let mut check_key = Zeroizing::new([0u8; CHECK_KEY_SIZE]);
+    share.copy_from_slice(&output[..SHARE_SIZE]);
+    check_key.copy_from_slice(&output[SHARE_SIZE..]);
+    Some((share, check_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap enough for debug builds
+    const TEST_COST: Cost = Cost { memory_kib: 64, iterations: 1, lanes: 1 };
+
+    #[test]
+    fn test_share_requires_the_passphrase() {
+        let (settings, share) = Passphrase::new(b"hunter2", PassphraseSource::Tty, [9; SALT_SIZE], TEST_COST).unwrap();
+        assert_eq!(settings.share(b"hunter2").as_deref(), Some(&*share));
+        assert_eq!(settings.share(b"hunter3"), None);
+        assert_eq!(settings.share(b""), None);
+
+        // Same passphrase, other salt: unrelated share
+        let (_, other) = Passphrase::new(b"hunter2", PassphraseSource::Tty, [8; SALT_SIZE], TEST_COST).unwrap();
+        assert_ne!(*share, *other);
+        let bytes = bincode::serialize(&settings).unwrap();
+        assert_eq!(crate::decode::<Passphrase>(&bytes), Some(settings));
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!("tty".parse(), Ok(PassphraseSource::Tty));
+        assert_eq!("env:OPS_SECRET".parse(), Ok(PassphraseSource::Env("OPS_SECRET".into())));
+        assert_eq!("fd:3".parse(), Ok(PassphraseSource::Fd(3)));
+        for source in [PassphraseSource::Tty, PassphraseSource::Env("X".into()), PassphraseSource::Fd(0)] {
+            assert_eq!(source.to_string().parse(), Ok(source));
+        }
+        assert!("fd:-1".parse::<PassphraseSource>().is_err());
+        assert!("env:".parse::<PassphraseSource>().is_err());
+        assert!("file:/etc/secret".parse::<PassphraseSource>().is_err());
+    }
+}
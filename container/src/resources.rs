@@ -0,0 +1,127 @@
+//! Files bundled with `rpack --resource`. They are compressed together into the
+//! `resources` section, encrypted with the payload key. The stub hands each one to the
+//! payload as a sealed memfd, so they never reach the disk in plaintext, and lists the
+//! descriptors in [`RESOURCES_ENV`] (see the `rpack-runtime` crate).
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Environment variable listing the bundled files of the running program, as
+/// `NAME=FD` entries separated by `:`.
+pub const RESOURCES_ENV: &str = "RPACK_RESOURCES";
+
+/// Longest name: memfd names are at most 249 bytes.
+pub const MAX_NAME_LEN: usize = 249;
+
+// LZ4 cannot expand its input more than ~255 times
+const MAX_LZ4_RATIO: usize = 256;
+
+/// A bundled file.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resource {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+// The contents may be secret
+impl fmt::Debug for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Resource({:?}, {} bytes)", self.name, self.data.len())
+    }
+}
+
+/// Whether `name` can name a resource: it appears in [`RESOURCES_ENV`] and as the
+/// name of the memfd.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN && !name.contains(['=', ':', '/', '\0'])
+}
+
+/// Plaintext of the `resources` section.
+pub fn compress(resources: &[Resource]) -> Vec<u8> {
+    let serialized = Zeroizing::new(bincode::serialize(resources).expect("Failed to serialize resources"));
+    lz4_flex::compress_prepend_size(&serialized)
+}
+
+/// Resources from the plaintext of the `resources` section, or None if it is corrupt.
+pub fn decompress(bytes: &[u8]) -> Option<Vec<Resource>> {
+    // Checked before the decompressed buffer is allocated
+    let size = u32::from_le_bytes(*bytes.first_chunk::<4>()?) as usize;
+    if size > bytes.len().saturating_mul(MAX_LZ4_RATIO) {
+        return None;
+    }
+    let serialized = Zeroizing::new(lz4_flex::decompress_size_prepended(bytes).ok()?);
+    let resources: Vec<Resource> = crate::decode(&serialized)?;
+    let mut names: Vec<&str> = resources.iter().map(|resource| resource.name.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    (names.len() == resources.len() && names.iter().all(|name| valid_name(name))).then_some(resources)
+}
+
+/// Value of [`RESOURCES_ENV`] for these names and descriptors.
+pub fn encode_env<'a>(entries: impl IntoIterator<Item = (&'a str, i32)>) -> String {
+    entries
+        .into_iter()
+        .map(|(name, fd)| format!("{}={}", name, fd))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Names and descriptors listed in a [`RESOURCES_ENV`] value. Malformed entries are skipped.
+pub fn decode_env(value: &str) -> impl Iterator<Item = (&str, i32)> {
+    value.split(':').filter_map(|entry| {
+        let (name, fd) = entry.split_once('=')?;
+        Some((name, fd.parse().ok().filter(|fd| *fd >= 0)?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(name: &str, data: &[u8]) -> Resource {
+        Resource { name: name.into(), data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_section_roundtrip() {
+        let resources = vec![resource("config.toml", b"threads = 4\n"), resource("empty", b""), resource("model.bin", &[7; 100_000])];
+        let section = compress(&resources);
+        assert!(section.len() < 1000);
+        assert_eq!(decompress(&section), Some(resources));
+        assert_eq!(decompress(&compress(&[])), Some(Vec::new()));
+
+        assert_eq!(decompress(&section[..section.len() / 2]), None);
+        assert_eq!(decompress(&[]), None);
+        // A forged size is rejected before anything is allocated
+        let mut forged = section.clone();
+        forged[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decompress(&forged), None);
+        // Names end up in the environment
+        assert_eq!(decompress(&compress(&[resource("a=b", b"")])), None);
+        assert_eq!(decompress(&compress(&[resource("a", b"1"), resource("a", b"2")])), None);
+    }
+
+    #[test]
+    fn test_env_roundtrip() {
+        let value = encode_env([("config.toml", 3), ("model.bin", 4)]);
+        assert_eq!(value, "config.toml=3:model.bin=4");
+        assert_eq!(decode_env(&value).collect::<Vec<_>>(), vec![("config.toml", 3), ("model.bin", 4)]);
+        assert_eq!(decode_env("a=x:b:c=-1:d=5").collect::<Vec<_>>(), vec![("d", 5)]);
+        assert_eq!(decode_env("").count(), 0);
+    }
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("model-v2.onnx"));
+        for name in ["", "a/b", "a:b", "a=b", "a\0b", &"x".repeat(MAX_NAME_LEN + 1)] {
+            assert!(!valid_name(name), "{:?}", name);
+        }
+    }
+}
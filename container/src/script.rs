@@ -0,0 +1,93 @@
+//! Interpreted payloads: a script packed like a binary, which the stub decrypts into a
+//! memfd and hands to its interpreter as `/proc/self/fd/N`.
+
+use serde::{Deserialize, Serialize};
+
+/// Longest shebang line the kernel reads, `#!` included.
+pub const MAX_SHEBANG_LEN: usize = 256;
+
+/// How the stub runs a script payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Script {
+    pub interpreter: Interpreter,
+    /// Single argument passed before the script, like the rest of a shebang line.
+    pub arg: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpreter {
+    /// Executable at this path on the machine the script runs on.
+    System(String),
+    /// Executable bundled as the resource of this name (see [`crate::resources`]).
+    Bundled(String),
+}
+
+impl Script {
+    /// Script run as its shebang line says, or None if `script` does not start with one.
+    /// Like the kernel, everything after the interpreter is a single argument.
+    pub fn from_shebang(script: &[u8]) -> Option<Script> {
+        let line = script.strip_prefix(b"#!")?;
+        let end = line.iter().position(|&byte| byte == b'\n')?;
+        if end + 2 > MAX_SHEBANG_LEN {
+            return None;
+        }
+        let line = std::str::from_utf8(&line[..end]).ok()?.trim_matches([' ', '\t', '\r']);
+        let (interpreter, arg) = match line.split_once([' ', '\t']) {
+            Some((interpreter, arg)) => (interpreter, Some(arg.trim_matches([' ', '\t']).to_string())),
+            None => (line, None),
+        };
+        (!interpreter.is_empty()).then(|| Script {
+            interpreter: Interpreter::System(interpreter.into()),
+            arg: arg.filter(|arg| !arg.is_empty()),
+        })
+    }
+
+    /// Command line of the interpreter for the script in descriptor `fd`, with the
+    /// user's arguments (argv without argv[0]).
+    pub fn argv<T: From<String>>(&self, fd: i32, user_args: impl IntoIterator<Item = T>) -> Vec<T> {
+        let name = match &self.interpreter {
+            Interpreter::System(path) => path.clone(),
+            Interpreter::Bundled(name) => name.clone(),
+        };
+        [name]
+            .into_iter()
+            .chain(self.arg.clone())
+            .chain([format!("/proc/self/fd/{}", fd)])
+            .map(T::from)
+            .chain(user_args)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(interpreter: &str, arg: Option<&str>) -> Script {
+        Script { interpreter: Interpreter::System(interpreter.into()), arg: arg.map(String::from) }
+    }
+
+    #[test]
+    fn test_from_shebang() {
+        assert_eq!(Script::from_shebang(b"#!/bin/sh\necho hi\n"), Some(system("/bin/sh", None)));
+        assert_eq!(Script::from_shebang(b"#! /usr/bin/env python3\r\n"), Some(system("/usr/bin/env", Some("python3"))));
+        // One argument, spaces included
+        assert_eq!(Script::from_shebang(b"#!/bin/sh -e -u\n"), Some(system("/bin/sh", Some("-e -u"))));
+        assert_eq!(Script::from_shebang(b"echo hi\n"), None);
+        assert_eq!(Script::from_shebang(b"#!\n"), None);
+        assert_eq!(Script::from_shebang(b"#!/bin/sh"), None);
+        let long = format!("#!/{}\n", "x".repeat(MAX_SHEBANG_LEN));
+        assert_eq!(Script::from_shebang(long.as_bytes()), None);
+    }
+
+    #[test]
+    fn test_argv() {
+        let user_args = || ["-v".to_string(), "file".to_string()];
+        assert_eq!(
+            system("/usr/bin/env", Some("python3")).argv(5, user_args()),
+            ["/usr/bin/env", "python3", "/proc/self/fd/5", "-v", "file"]
+        );
+        let bundled = Script { interpreter: Interpreter::Bundled("python3".into()), arg: None };
+        assert_eq!(bundled.argv(3, Vec::<String>::new()), ["python3", "/proc/self/fd/3"]);
+    }
+}
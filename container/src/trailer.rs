@@ -0,0 +1,254 @@
+//! Metadata written by the packer, at the end of the pack data.
+//!
+//! Pack data layout (the descriptor of the `rpack` note, see [`crate::elf`]):
+//! `[encrypted_payload] [a1] [a2] [white_data] [config] [decoy] [resources] [programs] [sizes] [checksums] [signature]`
+//!
+//! `sizes` holds nine little-endian `u64`: encrypted_payload, a1, a2, white_data, config,
+//! decoy, resources, programs and decompressed size. `decoy` is empty unless the packed binary
+//! runs a decoy on detection, `resources` unless files are bundled (see [`crate::resources`])
+//! and `programs` unless further executables are (see [`crate::bundle`]). `checksums` holds
+//! the BLAKE3 hashes of the original binary, of the compressed payload and of the AES key.
+//! `signature` is the Ed25519 signature of [`crate::elf::Pack::digest`]. Section offsets are
+//! relative to the start of the pack data.
+//!
+//! Everything here comes from the file, so [`Trailer::parse`] treats it as untrusted: all
+//! offsets use checked arithmetic and every section size is capped before anything is allocated.
+
+use crate::elf::MAX_PACK_DATA_SIZE;
+pub use checksum::signature::SIGNATURE_SIZE;
+use std::fmt;
+
+pub const BLAKE3_SIZE: usize = 32;
+pub const SIZE_FIELDS_SIZE: usize = 9 * 8;
+pub const CHECKSUMS_SIZE: usize = 3 * BLAKE3_SIZE;
+/// Bytes at the end of the pack data read by [`Trailer::parse`].
+pub const TRAILER_SIZE: usize = SIZE_FIELDS_SIZE + CHECKSUMS_SIZE + SIGNATURE_SIZE;
+
+/// The encrypted payload starts with the 16-byte AES-CTR counter block.
+pub const NONCE_SIZE: u64 = 16;
+
+// Generous bounds on what the packer produces. No section can outgrow the note holding
+// them all, which bounds the largest ones.
+pub const MAX_PAYLOAD_SIZE: u64 = MAX_PACK_DATA_SIZE;
+pub const MAX_VECTOR_SIZE: u64 = 1 << 20;
+pub const MAX_WHITE_DATA_SIZE: u64 = 1 << 28;
+pub const MAX_CONFIG_SIZE: u64 = 1 << 12;
+pub const MAX_DECOY_SIZE: u64 = 1 << 26;
+pub const MAX_RESOURCES_SIZE: u64 = MAX_PACK_DATA_SIZE;
+pub const MAX_PROGRAMS_SIZE: u64 = MAX_PACK_DATA_SIZE;
+/// Largest program the stub unpacks, which it holds in locked memory.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 1 << 30;
+// LZ4 cannot expand its input more than ~255 times
+const MAX_LZ4_RATIO: u64 = 256;
+
+/// Why a trailer was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailerError {
+    /// The pack data is too small to hold the trailer.
+    Truncated,
+    /// A section is larger than its limit.
+    TooLarge(&'static str, u64),
+    /// The sections do not fit between the start of the pack data and the trailer.
+    OutOfBounds,
+    /// The encrypted payload cannot even hold its counter block.
+    MissingNonce,
+    /// The decompressed size is impossible for the compressed payload.
+    BadDecompressedSize(u64),
+}
+
+impl fmt::Display for TrailerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrailerError::Truncated => write!(f, "pack data too small for the trailer"),
+            TrailerError::TooLarge(name, size) => write!(f, "{} section too large ({} bytes)", name, size),
+            TrailerError::OutOfBounds => write!(f, "sections do not fit in the pack data"),
+            TrailerError::MissingNonce => write!(f, "encrypted payload shorter than its nonce"),
+            TrailerError::BadDecompressedSize(size) => write!(f, "invalid decompressed size {}", size),
+        }
+    }
+}
+
+impl std::error::Error for TrailerError {}
+
+/// A byte range of the pack data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Section {
+    /// The bytes of this section in `image`, or None if it does not fit.
+    pub fn slice<'a>(&self, image: &'a [u8]) -> Option<&'a [u8]> {
+        let start = usize::try_from(self.offset).ok()?;
+        let len = usize::try_from(self.len).ok()?;
+        image.get(start..start.checked_add(len)?)
+    }
+}
+
+/// Parsed and bounds-checked trailer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailer {
+    pub payload: Section,
+    pub a1: Section,
+    pub a2: Section,
+    pub white_data: Section,
+    pub config: Section,
+    pub decoy: Section,
+    pub resources: Section,
+    pub programs: Section,
+    pub decompressed_size: u64,
+    pub original_hash: [u8; BLAKE3_SIZE],
+    pub compressed_hash: [u8; BLAKE3_SIZE],
+    pub aes_key_hash: [u8; BLAKE3_SIZE],
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+impl Trailer {
+    /// Parse the last bytes of pack data of `data_size` bytes.
+    ///
+    /// Only the last [`TRAILER_SIZE`] bytes of `tail` are used. On success, every section
+    /// lies within the pack data, before the trailer, and respects its size limit.
+    pub fn parse(tail: &[u8], data_size: u64) -> Result<Trailer, TrailerError> {
+        let tail = tail.len().checked_sub(TRAILER_SIZE).map(|start| &tail[start..]).ok_or(TrailerError::Truncated)?;
+        let mut sizes = [0u64; 9];
+        for (size, bytes) in sizes.iter_mut().zip(tail[..SIZE_FIELDS_SIZE].chunks_exact(8)) {
+            *size = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        let [size_payload, size_a1, size_a2, size_white_data, size_config, size_decoy, size_resources, size_programs, decompressed_size] =
+            sizes;
+
+        for (name, size, max) in [
+            ("payload", size_payload, MAX_PAYLOAD_SIZE),
+            ("a1", size_a1, MAX_VECTOR_SIZE),
+            ("a2", size_a2, MAX_VECTOR_SIZE),
+            ("white data", size_white_data, MAX_WHITE_DATA_SIZE),
+            ("config", size_config, MAX_CONFIG_SIZE),
+            ("decoy", size_decoy, MAX_DECOY_SIZE),
+            ("resources", size_resources, MAX_RESOURCES_SIZE),
+            ("programs", size_programs, MAX_PROGRAMS_SIZE),
+        ] {
+            if size > max {
+                return Err(TrailerError::TooLarge(name, size));
+            }
+        }
+        if size_payload < NONCE_SIZE {
+            return Err(TrailerError::MissingNonce);
+        }
+        let compressed_size = size_payload - NONCE_SIZE;
+        if decompressed_size > compressed_size.saturating_mul(MAX_LZ4_RATIO).min(MAX_DECOMPRESSED_SIZE) {
+            return Err(TrailerError::BadDecompressedSize(decompressed_size));
+        }
+
+        // Walk backwards from the trailer; any underflow means the sizes lie
+        let end = data_size.checked_sub(TRAILER_SIZE as u64).ok_or(TrailerError::Truncated)?;
+        let mut sections = [Section { offset: 0, len: 0 }; 8];
+        let mut offset = end;
+        for (section, len) in sections
+            .iter_mut()
+            .rev()
+            .zip([size_programs, size_resources, size_decoy, size_config, size_white_data, size_a2, size_a1, size_payload])
+        {
+            offset = offset.checked_sub(len).ok_or(TrailerError::OutOfBounds)?;
+            *section = Section { offset, len };
+        }
+        let [payload, a1, a2, white_data, config, decoy, resources, programs] = sections;
+
+        let hash = |index: usize| {
+            let start = SIZE_FIELDS_SIZE + index * BLAKE3_SIZE;
+            let mut hash = [0u8; BLAKE3_SIZE];
+            hash.copy_from_slice(&tail[start..start + BLAKE3_SIZE]);
+            hash
+        };
+        Ok(Trailer {
+            payload,
+            a1,
+            a2,
+            white_data,
+            config,
+            decoy,
+            resources,
+            programs,
+            decompressed_size,
+            original_hash: hash(0),
+            compressed_hash: hash(1),
+            aes_key_hash: hash(2),
+            signature: tail[TRAILER_SIZE - SIGNATURE_SIZE..].try_into().unwrap(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tail(sizes: [u64; 9]) -> Vec<u8> {
+        let mut tail: Vec<u8> = sizes.iter().flat_map(|size| size.to_le_bytes()).collect();
+        for i in 0..3 {
+            tail.extend_from_slice(&[i; BLAKE3_SIZE]);
+        }
+        tail.extend_from_slice(&[3; SIGNATURE_SIZE]);
+        tail
+    }
+
+    #[test]
+    fn test_parse_valid_trailer() {
+        let stub = 1000;
+        let sizes = [116, 20, 30, 400, 5, 50, 60, 70, 300];
+        let data_size = stub + sizes[..8].iter().sum::<u64>() + TRAILER_SIZE as u64;
+        let trailer = Trailer::parse(&tail(sizes), data_size).unwrap();
+        assert_eq!(trailer.payload, Section { offset: 1000, len: 116 });
+        assert_eq!(trailer.a1, Section { offset: 1116, len: 20 });
+        assert_eq!(trailer.a2, Section { offset: 1136, len: 30 });
+        assert_eq!(trailer.white_data, Section { offset: 1166, len: 400 });
+        assert_eq!(trailer.config, Section { offset: 1566, len: 5 });
+        assert_eq!(trailer.decoy, Section { offset: 1571, len: 50 });
+        assert_eq!(trailer.resources, Section { offset: 1621, len: 60 });
+        assert_eq!(trailer.programs, Section { offset: 1681, len: 70 });
+        assert_eq!(trailer.decompressed_size, 300);
+        let image = vec![0u8; data_size as usize];
+        assert_eq!(trailer.decoy.slice(&image).map(<[u8]>::len), Some(50));
+        assert_eq!(Section { offset: data_size, len: 1 }.slice(&image), None);
+        assert_eq!(trailer.original_hash, [0; BLAKE3_SIZE]);
+        assert_eq!(trailer.aes_key_hash, [2; BLAKE3_SIZE]);
+        assert_eq!(trailer.signature, [3; SIGNATURE_SIZE]);
+    }
+
+    #[test]
+    fn test_rejects_bad_sizes() {
+        let data_size = 1 << 20;
+        assert_eq!(Trailer::parse(&tail([0; 9])[1..], data_size), Err(TrailerError::Truncated));
+        assert_eq!(Trailer::parse(&tail([116, 0, 0, 0, 0, 0, 0, 0, 0]), 100), Err(TrailerError::Truncated));
+        assert_eq!(Trailer::parse(&tail([8, 0, 0, 0, 0, 0, 0, 0, 0]), data_size), Err(TrailerError::MissingNonce));
+        assert_eq!(
+            Trailer::parse(&tail([116, 0, 0, 0, u64::MAX, 0, 0, 0, 0]), data_size),
+            Err(TrailerError::TooLarge("config", u64::MAX))
+        );
+        assert_eq!(
+            Trailer::parse(&tail([116, 0, 0, 0, 0, 0, 0, 0, u64::MAX]), data_size),
+            Err(TrailerError::BadDecompressedSize(u64::MAX))
+        );
+        // Possible for LZ4, but more than the stub unpacks
+        assert_eq!(
+            Trailer::parse(&tail([MAX_PAYLOAD_SIZE, 0, 0, 0, 0, 0, 0, 0, MAX_DECOMPRESSED_SIZE + 1]), data_size),
+            Err(TrailerError::BadDecompressedSize(MAX_DECOMPRESSED_SIZE + 1))
+        );
+        assert_eq!(
+            Trailer::parse(&tail([116, 0, 0, 0, 0, MAX_DECOY_SIZE + 1, 0, 0, 0]), data_size),
+            Err(TrailerError::TooLarge("decoy", MAX_DECOY_SIZE + 1))
+        );
+        assert_eq!(
+            Trailer::parse(&tail([116, 0, 0, 0, 0, 0, MAX_RESOURCES_SIZE + 1, 0, 0]), data_size),
+            Err(TrailerError::TooLarge("resources", MAX_RESOURCES_SIZE + 1))
+        );
+        assert_eq!(
+            Trailer::parse(&tail([116, 0, 0, 0, 0, 0, 0, MAX_PROGRAMS_SIZE + 1, 0]), data_size),
+            Err(TrailerError::TooLarge("programs", MAX_PROGRAMS_SIZE + 1))
+        );
+        // Each size is within its limit but together they run past the start of the pack data
+        assert_eq!(
+            Trailer::parse(&tail([MAX_PAYLOAD_SIZE, MAX_VECTOR_SIZE, 0, 0, 0, 0, 0, 0, 0]), data_size),
+            Err(TrailerError::OutOfBounds)
+        );
+    }
+}
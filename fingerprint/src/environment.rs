@@ -0,0 +1,127 @@
+//! Identity of the machine the packed binary runs in, for `rpack --trust-env`: in a
+//! trusted environment, such as a build farm VM, the anti-VM checks are skipped.
+
+use crate::machine_id;
+use checksum::compute_blake3;
+use raw_cpuid::{cpuid, CpuId};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Values that identify a (virtual) machine. In an allowlist entry, a missing value
+/// matches any.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Environment {
+    /// Hypervisor vendor from CPUID leaf 0x40000000, e.g. `KVMKVMKVM`.
+    pub hypervisor: Option<String>,
+    /// DMI product name, e.g. `Standard PC (Q35 + ICH9, 2009)`.
+    pub dmi_product: Option<String>,
+    /// systemd / D-Bus machine ID.
+    pub machine_id: Option<String>,
+}
+
+impl Environment {
+    /// Environment of the current machine.
+    pub fn collect() -> Environment {
+        Environment {
+            hypervisor: hypervisor(),
+            dmi_product: fs::read_to_string("/sys/class/dmi/id/product_name")
+                .ok()
+                .map(|product| product.trim().to_string())
+                .filter(|product| !product.is_empty()),
+            machine_id: machine_id(),
+        }
+    }
+
+    /// Allowlist entry for this environment, or None if it names no value.
+    pub fn trust(&self) -> Option<TrustedEnvironment> {
+        let fields = Fields {
+            hypervisor: self.hypervisor.is_some(),
+            dmi_product: self.dmi_product.is_some(),
+            machine_id: self.machine_id.is_some(),
+        };
+        (fields != Fields::default()).then(|| TrustedEnvironment { digest: fields.digest(self), fields })
+    }
+}
+
+/// Which values an allowlist entry names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fields {
+    pub hypervisor: bool,
+    pub dmi_product: bool,
+    pub machine_id: bool,
+}
+
+impl Fields {
+    // Hash of the named values of `environment`, the others left out
+    fn digest(&self, environment: &Environment) -> [u8; 32] {
+        let mut canonical = String::from("rpack environment v1\n");
+        for (name, named, value) in [
+            ("hypervisor", self.hypervisor, &environment.hypervisor),
+            ("dmi-product", self.dmi_product, &environment.dmi_product),
+            ("machine-id", self.machine_id, &environment.machine_id),
+        ] {
+            if named {
+                canonical.push_str(&format!("{}={:?}\n", name, value));
+            }
+        }
+        compute_blake3(canonical.as_bytes())
+    }
+}
+
+/// Allowlist entry as stored in a packed binary: the values are hashed, so that the
+/// binary does not tell which machines it trusts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedEnvironment {
+    pub fields: Fields,
+    pub digest: [u8; 32],
+}
+
+impl TrustedEnvironment {
+    /// Whether `environment` has the values this entry names.
+    pub fn matches(&self, environment: &Environment) -> bool {
+        self.fields.digest(environment) == self.digest
+    }
+}
+
+fn hypervisor() -> Option<String> {
+    CpuId::new().get_hypervisor_info()?;
+    let leaf = cpuid!(0x4000_0000);
+    let bytes: Vec<u8> = [leaf.ebx, leaf.ecx, leaf.edx].iter().flat_map(|register| register.to_le_bytes()).collect();
+    let vendor = String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string();
+    (!vendor.is_empty()).then_some(vendor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ci_vm() -> Environment {
+        Environment {
+            hypervisor: Some("KVMKVMKVM".into()),
+            dmi_product: Some("Standard PC (Q35 + ICH9, 2009)".into()),
+            machine_id: Some("3d1219c7c4c5404aaa1f6d2a48adfda4".into()),
+        }
+    }
+
+    #[test]
+    fn test_trusted_environment() {
+        let exact = ci_vm().trust().unwrap();
+        assert!(exact.matches(&ci_vm()));
+        let mut other = ci_vm();
+        other.machine_id = Some("0".repeat(32));
+        assert!(!exact.matches(&other));
+
+        // Any KVM guest with that DMI product
+        let farm = Environment { machine_id: None, ..ci_vm() }.trust().unwrap();
+        assert!(farm.matches(&other));
+        assert!(!farm.matches(&Environment { hypervisor: None, ..ci_vm() }));
+
+        // An entry that names nothing would trust every machine
+        assert_eq!(Environment::default().trust(), None);
+    }
+
+    #[test]
+    fn test_collect_is_stable() {
+        assert_eq!(Environment::collect(), Environment::collect());
+    }
+}
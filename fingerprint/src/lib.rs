@@ -0,0 +1,156 @@
+//! Host fingerprint used to bind a packed binary to the machines it was packed for.
+
+use checksum::compute_blake3;
+use raw_cpuid::CpuId;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+pub mod environment;
+
+/// Identifiers of a host. Missing values are part of the fingerprint too, so a host
+/// must report exactly the same set to match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// systemd / D-Bus machine ID.
+    pub machine_id: Option<String>,
+    /// CPU vendor and brand string.
+    pub cpu: Option<String>,
+    /// MAC addresses of the physical network interfaces, uppercase and sorted.
+    pub macs: Vec<String>,
+    /// UUID of the filesystem mounted on `/`.
+    pub root_fs_uuid: Option<String>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of the current host.
+    pub fn collect() -> Fingerprint {
+        Fingerprint {
+            machine_id: machine_id(),
+            cpu: cpu(),
+            macs: macs(),
+            root_fs_uuid: root_fs_uuid(),
+        }
+    }
+
+    /// Hash of all the values, independent of the MAC order.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut macs = self.macs.clone();
+        macs.sort();
+        let mut canonical = String::from("rpack fingerprint v1\n");
+        for (name, value) in [
+            ("machine-id", self.machine_id.as_deref()),
+            ("cpu", self.cpu.as_deref()),
+            ("root-fs-uuid", self.root_fs_uuid.as_deref()),
+        ] {
+            canonical.push_str(&format!("{}={:?}\n", name, value));
+        }
+        for mac in &macs {
+            canonical.push_str(&format!("mac={}\n", mac));
+        }
+        compute_blake3(canonical.as_bytes())
+    }
+}
+
+/// XOR mask applied to the payload key for one host. Both the whitebox-protected key
+/// and the host fingerprint are needed to recompute it.
+pub fn host_key_mask(whitebox_key: &[u8; 16], fingerprint_digest: &[u8; 32]) -> [u8; 16] {
+    let mut input = b"rpack host key v1".to_vec();
+    input.extend_from_slice(whitebox_key);
+    input.extend_from_slice(fingerprint_digest);
+    let mut mask = [0u8; 16];
+    mask.copy_from_slice(&compute_blake3(&input)[..16]);
+    mask
+}
+
+pub(crate) fn machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_string())
+        .find(|id| !id.is_empty())
+}
+
+fn cpu() -> Option<String> {
+    let cpuid = CpuId::new();
+    let vendor = cpuid.get_vendor_info()?;
+    let brand = cpuid.get_processor_brand_string();
+    Some(format!("{} {}", vendor.as_str(), brand.as_ref().map_or("", |brand| brand.as_str().trim())))
+}
+
+// Same source as the stub's MAC check, restricted to interfaces backed by a device:
+// bridges, veths and tunnels come and go.
+fn macs() -> Vec<String> {
+    let mut macs = Vec::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/net") {
+        for entry in entries.flatten() {
+            if !entry.path().join("device").exists() {
+                continue;
+            }
+            if let Ok(mac) = fs::read_to_string(entry.path().join("address")) {
+                let mac = mac.trim().to_uppercase();
+                if !mac.is_empty() && mac != "00:00:00:00:00:00" {
+                    macs.push(mac);
+                }
+            }
+        }
+    }
+    macs.sort();
+    macs.dedup();
+    macs
+}
+
+// udev links /dev/disk/by-uuid/<uuid> to each block device with a filesystem UUID
+fn root_fs_uuid() -> Option<String> {
+    let root_device = fs::metadata("/").ok()?.dev();
+    fs::read_dir("/dev/disk/by-uuid")
+        .ok()?
+        .flatten()
+        .find(|entry| fs::metadata(entry.path()).is_ok_and(|device| device.rdev() == root_device))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Fingerprint {
+        Fingerprint {
+            machine_id: Some("3d1219c7c4c5404aaa1f6d2a48adfda4".into()),
+            cpu: Some("GenuineIntel Intel(R) Xeon(R)".into()),
+            macs: vec!["02:FC:00:00:00:01".into(), "06:65:F0:14:75:6D".into()],
+            root_fs_uuid: None,
+        }
+    }
+
+    #[test]
+    fn test_collect_is_stable() {
+        assert_eq!(Fingerprint::collect().digest(), Fingerprint::collect().digest());
+    }
+
+    #[test]
+    fn test_digest() {
+        let fingerprint = sample();
+        let mut reordered = sample();
+        reordered.macs.reverse();
+        assert_eq!(reordered.digest(), fingerprint.digest());
+
+        let mut other = sample();
+        other.machine_id = None;
+        assert_ne!(other.digest(), fingerprint.digest());
+        let mut other = sample();
+        other.root_fs_uuid = Some(String::new());
+        assert_ne!(other.digest(), fingerprint.digest());
+        let mut other = sample();
+        other.macs.pop();
+        assert_ne!(other.digest(), fingerprint.digest());
+    }
+
+    #[test]
+    fn test_host_key_mask() {
+        let digest = sample().digest();
+        assert_eq!(host_key_mask(&[1; 16], &digest), host_key_mask(&[1; 16], &digest));
+        assert_ne!(host_key_mask(&[1; 16], &digest), host_key_mask(&[2; 16], &digest));
+        assert_ne!(host_key_mask(&[1; 16], &digest), host_key_mask(&[1; 16], &[0; 32]));
+    }
+}
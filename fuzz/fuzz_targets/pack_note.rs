@@ -0,0 +1,12 @@
+#![no_main]
+
+use container::elf::find_pack_in_file;
+use libfuzzer_sys::fuzz_target;
+
+// Input: a packed binary whose program headers point anywhere
+fuzz_target!(|data: &[u8]| {
+    if let Some(pack) = find_pack_in_file(data) {
+        let start = pack.data.as_ptr() as usize - data.as_ptr() as usize;
+        assert!(start + pack.data.len() <= data.len());
+    }
+});
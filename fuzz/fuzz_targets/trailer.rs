@@ -0,0 +1,23 @@
+#![no_main]
+
+use container::trailer::{Trailer, TRAILER_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+// Input: 8-byte file size followed by the tail of the file
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let file_size = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let tail = &data[8..];
+    if let Ok(trailer) = Trailer::parse(tail, file_size) {
+        let end = file_size - TRAILER_SIZE as u64;
+        let sections = [trailer.payload, trailer.a1, trailer.a2, trailer.white_data, trailer.config, trailer.decoy, trailer.resources, trailer.programs];
+        let mut offset = sections[0].offset;
+        for section in sections {
+            assert_eq!(section.offset, offset);
+            offset += section.len;
+        }
+        assert_eq!(offset, end);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(unpadded) = aes::unpad_pkcs7(data) {
+        let padding = &data[unpadded.len()..];
+        assert!(!padding.is_empty() && padding.len() <= 16);
+        assert!(padding.iter().all(|&byte| byte as usize == padding.len()));
+        assert_eq!(&data[..unpadded.len()], unpadded.as_slice());
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use whitebox::{DecryptContext, WhiteData};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(white_data) = container::decode::<WhiteData>(data) {
+        // Same checks the stub runs before using the tables
+        let _ = DecryptContext::new(&white_data, white_data.mask.len(), 1231873);
+    }
+});
@@ -1,17 +1,15 @@
 use std::process::Command;
 use std::fs;
 
-fn main() {
-    println!("cargo:rerun-if-changed=../stub/src/main.rs");
-
-    // Build the stub with release optimizations
+// Build the stub with release optimizations, then copy and strip it to `output`
+fn build_stub(features: &[&str], target_dir: &str, output: &str) {
+    let mut args = vec!["build", "--release", "--manifest-path", "../stub/Cargo.toml", "--target-dir", target_dir];
+    if !features.is_empty() {
+        args.push("--features");
+        args.extend_from_slice(features);
+    }
     let build_status = Command::new("cargo")
-        .args(&[
-            "build",
-            "--release",
-            "--manifest-path",
-            "../stub/Cargo.toml",
-        ])
+        .args(&args)
         .env("RUSTFLAGS", "-C lto=yes -C codegen-units=1 -C debuginfo=0")
         .status()
         .expect("Failed to build stub");
@@ -21,12 +19,13 @@ fn main() {
     }
 
     // Copy the binary
-    fs::copy("../target/release/stub", "../target/stub.bin")
+    let binary = format!("{}/release/stub", target_dir);
+    fs::copy(&binary, output)
         .expect("Failed to copy stub binary");
 
     // Strip the binary
     let strip_status = Command::new("strip")
-        .arg("../target/stub.bin")
+        .arg(output)
         .status()
         .expect("Failed to execute strip command");
 
@@ -34,5 +33,18 @@ fn main() {
         panic!("Failed to strip stub binary");
     }
 
-    println!("cargo:rerun-if-changed=../target/release/stub");
+    println!("cargo:rerun-if-changed={}", binary);
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=../stub/src/main.rs");
+    println!("cargo:rerun-if-changed=../stub/Cargo.toml");
+    // The stub embeds these crates, and the packer writes their formats
+    for dir in ["../container/src", "../whitebox/src", "../aes/src", "../checksum/src", "../fingerprint/src"] {
+        println!("cargo:rerun-if-changed={}", dir);
+    }
+
+    build_stub(&[], "../target", "../target/stub.bin");
+    // Separate target directory so the two variants do not invalidate each other
+    build_stub(&["diagnostics"], "../target/diagnostics", "../target/stub-diagnostics.bin");
 }
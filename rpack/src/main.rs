@@ -1,39 +1,655 @@
-use std::env;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::os::unix::fs::PermissionsExt;
-use aes::AES128;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use aes::io::EncryptWriter;
+use aes::{Encryptor, Mode, AES128};
 use lz4_flex::compress;
-use whitebox::{create_whitebox, encrypt_func};
+use whitebox::{create_whitebox, encrypt_func, DecryptContext};
+use checksum::signature::{self, PUBLIC_KEY_SIZE, SECRET_KEY_SIZE, SIGNATURE_SIZE};
 use checksum::{compute_blake3, hash_to_hex};
+use clap::{Args, Parser, Subcommand};
+use container::audit::{AuditTarget, Event, Summary};
+use container::bundle::{self, Program};
+use container::license::{License, SignedLicense};
+use container::passphrase::{Cost, Passphrase, PassphraseSource};
+use container::resources::{self, Resource};
+use container::script::{Interpreter, Script};
+use container::elf::{embed, file_program_headers, file_segment, find_pack_in_file, text_hashes};
+use container::trailer::{Trailer, MAX_CONFIG_SIZE, MAX_DECOMPRESSED_SIZE, MAX_PAYLOAD_SIZE, MAX_PROGRAMS_SIZE, MAX_RESOURCES_SIZE};
+use container::{find_public_key, Config, Reaction};
+use fingerprint::environment::{Environment, TrustedEnvironment};
+use fingerprint::{host_key_mask, Fingerprint};
+use zeroize::Zeroizing;
 use ndarray::Array1;
-use bincode;
 
 const STUB_DATA: &[u8] = include_bytes!("../../target/stub.bin");
+// Same stub, built with the `diagnostics` feature
+const DIAGNOSTICS_STUB_DATA: &[u8] = include_bytes!("../../target/stub-diagnostics.bin");
+// Passphrase for --passphrase-from, when not typed on the terminal
+const PASSPHRASE_ENV: &str = "RPACK_PASSPHRASE";
+// Default decoy: a Tigress-obfuscated program that segfaults (source in BIGMONKE.c)
+const DEFAULT_DECOY: &[u8] = include_bytes!("BIGMONKE");
 
 fn validate_elf(data: &[u8]) -> bool {
     data.len() >= 4 && &data[0..4] == b"\x7FELF"
 }
 
+#[derive(Parser)]
+#[command(about = "ELF packer", args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    pack: PackArgs,
+}
+
+#[derive(Args)]
+struct PackArgs {
+    /// ELF binary, or script starting with a `#!` line, to pack
+    #[arg(required = true)]
+    input_binary: Option<String>,
+    /// Where to write the packed binary
+    #[arg(required = true)]
+    output_packed_binary: Option<String>,
+    /// Do not run the anti-VM checks when the packed binary starts
+    #[arg(long)]
+    no_anti_vm: bool,
+    /// Refuse to run in Docker, Podman or LXC containers
+    #[arg(long)]
+    anti_container: bool,
+    /// Do not run the emulator checks (qemu-user, unicorn) when the packed binary starts
+    #[arg(long)]
+    no_anti_emulator: bool,
+    /// Do not run the weighted debugger checks when the packed binary starts
+    #[arg(long)]
+    no_anti_debugger: bool,
+    /// Skip the VM, container and emulator checks in the environment described by this
+    /// file (see `rpack environment`). Remove values from it to trust more machines.
+    /// Repeatable.
+    #[arg(long, value_name = "ENVIRONMENT")]
+    trust_env: Vec<PathBuf>,
+    /// Let the packed binary run when /proc is not mounted instead of treating it as tampering
+    #[arg(long)]
+    allow_missing_proc: bool,
+    /// Ed25519 secret key to sign the packed binary with (see `rpack keygen`)
+    #[arg(long, env = "RPACK_SIGNING_KEY", value_name = "PATH")]
+    signing_key: Option<PathBuf>,
+    /// Sign with a throwaway key instead of --signing-key. Nobody can check where such a
+    /// binary comes from afterwards.
+    #[arg(long, conflicts_with = "signing_key")]
+    ephemeral_key: bool,
+    /// What the packed binary does when it detects tampering, a debugger or a VM:
+    /// exit[:CODE], decoy, corrupt[:MAX_DELAY_MS] (crash after a random delay) or log
+    #[arg(long, value_name = "REACTION", default_value_t = Reaction::Decoy)]
+    on_detection: Reaction,
+    /// ELF program run by the `decoy` reaction instead of the built-in one
+    #[arg(long, value_name = "PATH")]
+    decoy: Option<PathBuf>,
+    /// Use the diagnostic stub, which prints an error code and reason when it refuses
+    /// to run, and which anti-VM checks fired. Not for release builds.
+    #[arg(long)]
+    diagnostics: bool,
+    /// Only let the packed binary decrypt on the host whose fingerprint is in this file
+    /// (see `rpack fingerprint`). Repeat to allow several hosts.
+    #[arg(long, value_name = "FINGERPRINT")]
+    bind_to: Vec<PathBuf>,
+    /// Refuse to run before this date (YYYY-MM-DD, UTC) or unix time
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    not_before: Option<u64>,
+    /// Refuse to run after this date (YYYY-MM-DD, UTC, inclusive) or from this unix time
+    #[arg(long, value_name = "DATE", value_parser = parse_end_date)]
+    not_after: Option<u64>,
+    /// Only run on the host whose fingerprint is in this file. Unlike --bind-to, this is
+    /// a license check: the payload key does not depend on it. Repeat to allow several hosts.
+    #[arg(long, value_name = "FINGERPRINT")]
+    license_host: Vec<PathBuf>,
+    /// Feature flag for the packed program to read from its license. Repeatable.
+    #[arg(long = "feature", value_name = "NAME")]
+    features: Vec<String>,
+    /// Who the license is issued to
+    #[arg(long, value_name = "NAME")]
+    licensee: Option<String>,
+    /// Printed when the license does not allow running
+    #[arg(long, value_name = "TEXT", default_value = "This program's license does not allow it to run here or now.")]
+    license_message: String,
+    /// Require a passphrase to run, read from tty, env:NAME or fd:N. The passphrase
+    /// itself is read from RPACK_PASSPHRASE, or asked for on the terminal.
+    #[arg(long, value_name = "SOURCE")]
+    passphrase_from: Option<PassphraseSource>,
+    /// Keep the stub running as a guardian that traces the program, so that no debugger
+    /// can attach to it. Signals, stops and the exit status are passed through.
+    #[arg(long)]
+    guardian: bool,
+    /// Instead of reacting to detections, record the result of every check to
+    /// file:PATH, syslog or syslog:SOCKET and keep running (see `rpack audit-summary`)
+    #[arg(long, value_name = "TARGET")]
+    audit: Option<AuditTarget>,
+    /// Bundle this file, encrypted like the program, which receives it as a sealed memfd
+    /// listed in RPACK_RESOURCES. Named after the file unless given as NAME=PATH. Repeatable.
+    #[arg(long = "resource", value_name = "[NAME=]PATH")]
+    resources: Vec<String>,
+    /// Also pack this executable, run instead of the main one when the packed binary is
+    /// invoked as NAME (e.g. through a symlink) or with NAME as its first argument.
+    /// Named after the file unless given as NAME=PATH. Repeatable.
+    #[arg(long = "program", value_name = "[NAME=]PATH")]
+    programs: Vec<String>,
+    /// Run the script being packed with the interpreter at this path on the target
+    /// machine, instead of the one on its `#!` line
+    #[arg(long, value_name = "PATH")]
+    interpreter: Option<String>,
+    /// Bundle this ELF interpreter, like --resource, and run the script being packed
+    /// with it instead of the one on its `#!` line
+    #[arg(long, value_name = "[NAME=]PATH", conflicts_with = "interpreter")]
+    bundle_interpreter: Option<String>,
+    /// Argument passed to the interpreter before the script, instead of the rest of the
+    /// `#!` line. Without it, --interpreter and --bundle-interpreter pass none.
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    interpreter_arg: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate an Ed25519 signing key and print its public key
+    Keygen {
+        /// Where to write the secret key (hex)
+        secret_key: PathBuf,
+    },
+    /// Print the fingerprint of this host, to pass to --bind-to
+    Fingerprint {
+        /// Write it to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print what identifies this machine, to pass to --trust-env
+    Environment {
+        /// Write it to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Tally the records of binaries packed with --audit
+    AuditSummary {
+        /// Files written by --audit file:PATH, or syslog files
+        #[arg(required = true)]
+        logs: Vec<PathBuf>,
+    },
+    /// Check the signature of a packed binary without running it
+    Verify {
+        packed_binary: PathBuf,
+        /// Fail unless the binary is signed with this public key (hex)
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input_binary> <output_packed_binary>", args[0]);
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Keygen { secret_key }) => keygen(&secret_key),
+        Some(Command::Fingerprint { output }) => write_json(&Fingerprint::collect(), output.as_deref()),
+        Some(Command::Environment { output }) => write_json(&Environment::collect(), output.as_deref()),
+        Some(Command::AuditSummary { logs }) => audit_summary(&logs),
+        Some(Command::Verify { packed_binary, public_key }) => {
+            let expected = public_key.map(|key| match parse_hex::<PUBLIC_KEY_SIZE>(&key) {
+                Some(key) => key,
+                None => {
+                    eprintln!("Error: --public-key must be {} hex bytes", PUBLIC_KEY_SIZE);
+                    std::process::exit(1);
+                }
+            });
+            match verify(&packed_binary, expected.as_ref()) {
+                Ok(key) => println!("Signature OK, signed with public key {}", hash_to_hex(&key)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => pack(cli.pack),
+    }
+}
+
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != 2 * N || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn read_secret_key(path: &Path) -> [u8; SECRET_KEY_SIZE] {
+    let text = std::fs::read_to_string(path).expect("Failed to read signing key");
+    match parse_hex(&text) {
+        Some(key) => key,
+        None => {
+            eprintln!("Error: {} is not a {}-byte hex signing key", path.display(), SECRET_KEY_SIZE);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Days between 1970-01-01 and a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// YYYY-MM-DD (midnight UTC) or unix seconds
+fn parse_date(text: &str) -> Result<u64, String> {
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+    let invalid = || format!("'{}' is neither YYYY-MM-DD nor unix seconds", text);
+    let mut parts = text.splitn(3, '-');
+    let mut next = |len: usize| {
+        parts.next().filter(|part| part.len() == len).and_then(|part| part.parse::<u32>().ok()).ok_or_else(invalid)
+    };
+    let (year, month, day) = (next(4)?, next(2)?, next(2)?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return Err(invalid()),
+    };
+    if day == 0 || day > days_in_month || year < 1970 {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(i64::from(year), month, day) as u64 * 86_400)
+}
+
+// Like parse_date, but a date includes its whole day
+fn parse_end_date(text: &str) -> Result<u64, String> {
+    let seconds = parse_date(text)?;
+    Ok(if text.contains('-') { seconds + 86_400 } else { seconds })
+}
+
+// The passphrase to pack with, from the environment or typed twice
+fn read_passphrase() -> Zeroizing<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Zeroizing::new(passphrase);
+    }
+    let prompt = |prompt| Zeroizing::new(rpassword::prompt_password(prompt).expect("Failed to read passphrase"));
+    let passphrase = prompt("Passphrase: ");
+    if *passphrase != *prompt("Repeat passphrase: ") {
+        eprintln!("Error: passphrases do not match");
         std::process::exit(1);
     }
-    let input_path = &args[1];
-    let output_path = &args[2];
+    passphrase
+}
+
+// Print `value` as JSON, or write it to `output`
+fn write_json(value: &impl serde::Serialize, output: Option<&Path>) {
+    let json = serde_json::to_string_pretty(value).expect("Failed to serialize JSON");
+    match output {
+        Some(path) => std::fs::write(path, json + "\n").expect("Failed to write JSON"),
+        None => println!("{}", json),
+    }
+}
+
+fn read_fingerprint(path: &Path) -> Fingerprint {
+    let json = std::fs::read_to_string(path).expect("Failed to read fingerprint");
+    match serde_json::from_str(&json) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            eprintln!("Error: {} is not a fingerprint from `rpack fingerprint`: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn read_environment(path: &Path) -> TrustedEnvironment {
+    let json = std::fs::read_to_string(path).expect("Failed to read environment");
+    let environment: Environment = match serde_json::from_str(&json) {
+        Ok(environment) => environment,
+        Err(e) => {
+            eprintln!("Error: {} is not an environment from `rpack environment`: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    match environment.trust() {
+        Some(trusted) => trusted,
+        None => {
+            eprintln!("Error: {} names no value, which would trust every machine", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+// NAME=PATH, or PATH named after the file
+fn named_path(spec: &str) -> (String, &Path) {
+    match spec.split_once('=') {
+        Some((name, path)) => (name.to_string(), Path::new(path)),
+        None => {
+            let path = Path::new(spec);
+            (path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()), path)
+        }
+    }
+}
+
+// Files for --resource
+fn read_resources<'a>(specs: impl IntoIterator<Item = &'a String>) -> Vec<Resource> {
+    let mut resources: Vec<Resource> = Vec::new();
+    for spec in specs {
+        let (name, path) = named_path(spec);
+        if !resources::valid_name(&name) {
+            eprintln!("Error: invalid resource name '{}' (1 to {} bytes, without '=', ':' or '/')", name, resources::MAX_NAME_LEN);
+            std::process::exit(1);
+        }
+        if resources.iter().any(|resource| resource.name == name) {
+            eprintln!("Error: two resources named '{}'", name);
+            std::process::exit(1);
+        }
+        let data = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error: failed to read resource {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        resources.push(Resource { name, data });
+    }
+    resources
+}
+
+// Executables for --program, with their names
+fn read_programs(specs: &[String]) -> Vec<(String, Vec<u8>)> {
+    let mut programs: Vec<(String, Vec<u8>)> = Vec::new();
+    for spec in specs {
+        let (name, path) = named_path(spec);
+        if !bundle::valid_name(&name) {
+            eprintln!("Error: invalid program name '{}' (must be a file name)", name);
+            std::process::exit(1);
+        }
+        if programs.iter().any(|(other, _)| *other == name) {
+            eprintln!("Error: two programs named '{}'", name);
+            std::process::exit(1);
+        }
+        let data = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error: failed to read program {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        if !validate_elf(&data) {
+            eprintln!("Error: {} is not a valid ELF binary", path.display());
+            std::process::exit(1);
+        }
+        if data.len() as u64 > MAX_DECOMPRESSED_SIZE {
+            eprintln!("Error: program {} too large ({} bytes, at most {})", path.display(), data.len(), MAX_DECOMPRESSED_SIZE);
+            std::process::exit(1);
+        }
+        programs.push((name, data));
+    }
+    programs
+}
+
+// Syslog prefixes the records, and other programs log to the same files
+fn audit_summary(paths: &[PathBuf]) {
+    let mut events = Vec::new();
+    for path in paths {
+        let log = std::fs::read_to_string(path).expect("Failed to read audit log");
+        events.extend(
+            log.lines()
+                .filter_map(|line| line.find('{').map(|start| &line[start..]))
+                .filter_map(|record| serde_json::from_str::<Event>(record).ok()),
+        );
+    }
+    if events.is_empty() {
+        eprintln!("Error: no audit records found");
+        std::process::exit(1);
+    }
+    print!("{}", Summary::new(&events));
+}
+
+fn keygen(path: &Path) {
+    let secret_key: [u8; SECRET_KEY_SIZE] = rand::random();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .expect("Failed to create secret key file");
+    writeln!(file, "{}", hash_to_hex(&secret_key)).expect("Failed to write secret key");
+    println!("Secret key written to {}", path.display());
+    println!("Public key: {}", hash_to_hex(&signature::public_key(&secret_key)));
+}
+
+// Check the signature of a packed binary with the key embedded in its stub, and
+// optionally that this key is the expected one. Returns the embedded key.
+fn verify(path: &Path, expected: Option<&[u8; PUBLIC_KEY_SIZE]>) -> Result<[u8; PUBLIC_KEY_SIZE], String> {
+    let image = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let pack = find_pack_in_file(&image).ok_or("no pack data in the ELF program headers")?;
+    let trailer = Trailer::parse(pack.data, pack.data.len() as u64).map_err(|e| format!("invalid trailer: {}", e))?;
+    let stub = &image[..pack.segment.offset as usize];
+    let key_offset = find_public_key(stub).ok_or("no public key in the stub")?;
+    let key: [u8; PUBLIC_KEY_SIZE] = stub[key_offset..key_offset + PUBLIC_KEY_SIZE].try_into().unwrap();
+
+    if !signature::verify(&key, &pack.digest, &trailer.signature) {
+        return Err("signature does not match the binary".into());
+    }
+    if expected.is_some_and(|expected| *expected != key) {
+        return Err(format!("signed with public key {}, not the expected one", hash_to_hex(&key)));
+    }
+    Ok(key)
+}
+
+// The decoy is stored as [key] [counter block] [AES-128-CTR ciphertext]. The key is
+// not a secret: encryption only keeps the decoy from showing up in the packed file.
+fn encrypt_decoy(decoy: &[u8]) -> Vec<u8> {
+    if decoy.is_empty() {
+        return Vec::new();
+    }
+    let key = AES128::generate_key();
+    let nonce = AES128::generate_iv();
+    let mut header = key.to_vec();
+    header.extend_from_slice(&nonce);
+    let mut writer = EncryptWriter::new(header, Encryptor::new(AES128::new(&key), Mode::Ctr(nonce)));
+    writer.write_all(decoy).expect("Failed to encrypt decoy");
+    writer.finish().expect("Failed to encrypt decoy")
+}
+
+// [counter block] [AES-128-CTR ciphertext]
+fn encrypt_payload(compressed_data: &[u8], key: &[u8; 16]) -> Vec<u8> {
+    let nonce = AES128::generate_iv();
+    let mut writer = EncryptWriter::new(nonce.to_vec(), Encryptor::new(AES128::new(key), Mode::Ctr(nonce)));
+    for chunk in compressed_data.chunks(64 * 1024) {
+        writer.write_all(chunk).expect("Failed to encrypt compressed data");
+    }
+    writer.finish().expect("Failed to encrypt compressed data")
+}
+
+// Further executables, each compressed and encrypted like the payload with a key of its
+// own, wrapped by the payload key
+fn encrypt_programs(programs: &[(String, Vec<u8>)], payload_key: &[u8; 16]) -> Vec<u8> {
+    if programs.is_empty() {
+        return Vec::new();
+    }
+    println!("[*] Compressing and encrypting {} more program(s)...", programs.len());
+    let mut index = Vec::new();
+    let mut encrypted = Vec::new();
+    let mut offset = 0;
+    for (name, data) in programs {
+        let key = AES128::generate_key();
+        let mask = bundle::key_mask(payload_key, name);
+        let compressed_data = compress(data);
+        let program = encrypt_payload(&compressed_data, &key);
+        if program.len() as u64 > MAX_PAYLOAD_SIZE {
+            eprintln!("Error: program '{}' too large ({} bytes compressed, at most {})", name, program.len(), MAX_PAYLOAD_SIZE);
+            std::process::exit(1);
+        }
+        index.push(Program {
+            name: name.clone(),
+            wrapped_key: std::array::from_fn(|i| key[i] ^ mask[i]),
+            offset,
+            len: program.len() as u64,
+            decompressed_size: data.len() as u64,
+            original_hash: compute_blake3(data),
+            compressed_hash: compute_blake3(&compressed_data),
+        });
+        offset += program.len() as u64;
+        encrypted.push(program);
+    }
+    let section = bundle::encode(&index, &encrypted);
+    if section.len() as u64 > MAX_PROGRAMS_SIZE {
+        eprintln!("Error: programs too large ({} bytes compressed, at most {})", section.len(), MAX_PROGRAMS_SIZE);
+        std::process::exit(1);
+    }
+    section
+}
+
+// Bundled files, encrypted with the payload key: [counter block] [AES-128-CTR ciphertext].
+// Their integrity rests on the signature.
+fn encrypt_resources(bundled: &[Resource], key: &[u8; 16]) -> Vec<u8> {
+    if bundled.is_empty() {
+        return Vec::new();
+    }
+    println!("[*] Compressing and encrypting {} resource(s)...", bundled.len());
+    // The size prefix of the compressed section is 32 bits
+    let size: usize = bundled.iter().map(|resource| resource.name.len() + resource.data.len() + 16).sum();
+    if size > u32::MAX as usize {
+        eprintln!("Error: resources too large ({} bytes, at most {})", size, u32::MAX);
+        std::process::exit(1);
+    }
+    let nonce = AES128::generate_iv();
+    let mut writer = EncryptWriter::new(nonce.to_vec(), Encryptor::new(AES128::new(key), Mode::Ctr(nonce)));
+    writer.write_all(&resources::compress(bundled)).expect("Failed to encrypt resources");
+    let encrypted = writer.finish().expect("Failed to encrypt resources");
+    if encrypted.len() as u64 > MAX_RESOURCES_SIZE {
+        eprintln!("Error: resources too large ({} bytes compressed, at most {})", encrypted.len(), MAX_RESOURCES_SIZE);
+        std::process::exit(1);
+    }
+    encrypted
+}
+
+fn pack(args: PackArgs) {
+    // Both are required when there is no subcommand
+    let input_path = &args.input_binary.unwrap();
+    let output_path = &args.output_packed_binary.unwrap();
+    let mut config = Config {
+        anti_vm: !args.no_anti_vm,
+        anti_container: args.anti_container,
+        anti_emulator: !args.no_anti_emulator,
+        anti_debugger: !args.no_anti_debugger,
+        trusted_environments: args.trust_env.iter().map(|path| read_environment(path)).collect(),
+        allow_missing_proc: args.allow_missing_proc,
+        reaction: args.on_detection,
+        host_keys: Vec::new(),
+        license: None,
+        passphrase: None,
+        guardian: args.guardian,
+        text_hashes: Vec::new(),
+        audit: args.audit,
+        script: None,
+    };
+    let hosts: Vec<Fingerprint> = args.bind_to.iter().map(|path| read_fingerprint(path)).collect();
+    let decoy = match (&args.decoy, config.reaction) {
+        (Some(path), Reaction::Decoy) => {
+            let decoy = std::fs::read(path).expect("Failed to read decoy");
+            if !validate_elf(&decoy) {
+                eprintln!("Error: Decoy is not a valid ELF binary");
+                std::process::exit(1);
+            }
+            decoy
+        }
+        (None, Reaction::Decoy) => DEFAULT_DECOY.to_vec(),
+        (Some(_), _) => {
+            eprintln!("Error: --decoy requires --on-detection decoy");
+            std::process::exit(1);
+        }
+        (None, _) => Vec::new(),
+    };
+    let secret_key = Zeroizing::new(match &args.signing_key {
+        Some(path) => read_secret_key(path),
+        None if args.ephemeral_key => {
+            eprintln!("Warning: signing with a throwaway key");
+            rand::random()
+        }
+        None => {
+            eprintln!("Error: no signing key given. Create one with `rpack keygen` and pass it with --signing-key or RPACK_SIGNING_KEY, or pass --ephemeral-key to sign with a throwaway key");
+            std::process::exit(1);
+        }
+    });
+    let public_key = signature::public_key(&secret_key);
+
+    let mut stub = if args.diagnostics {
+        eprintln!("Warning: using the diagnostic stub, which explains its failures on stderr");
+        DIAGNOSTICS_STUB_DATA.to_vec()
+    } else {
+        STUB_DATA.to_vec()
+    };
+    let key_offset = find_public_key(&stub).expect("Stub has no public key slot");
+    stub[key_offset..key_offset + PUBLIC_KEY_SIZE].copy_from_slice(&public_key);
+    // The stub compares its code in memory with these while unpacking
+    config.text_hashes = file_program_headers(&stub)
+        .and_then(|headers| text_hashes(&headers, file_segment(&stub)))
+        .expect("Failed to hash the stub's executable segments");
+
+    let licensed = args.not_before.is_some()
+        || args.not_after.is_some()
+        || !args.license_host.is_empty()
+        || !args.features.is_empty()
+        || args.licensee.is_some();
+    if licensed {
+        let license = License {
+            licensee: args.licensee,
+            not_before: args.not_before,
+            not_after: args.not_after,
+            hosts: args.license_host.iter().map(|path| read_fingerprint(path).digest()).collect(),
+            features: args.features,
+            message: args.license_message,
+        };
+        config.license = Some(SignedLicense::sign(&license, &secret_key));
+    }
+
+    // A bundled interpreter is a bundled file like the others
+    let bundled = read_resources(args.resources.iter().chain(&args.bundle_interpreter));
+    let bundled_interpreter = args.bundle_interpreter.as_ref().map(|spec| named_path(spec).0);
+    if let Some(name) = &bundled_interpreter {
+        if !bundled.iter().any(|resource| &resource.name == name && validate_elf(&resource.data)) {
+            eprintln!("Error: bundled interpreter '{}' is not a valid ELF binary", name);
+            std::process::exit(1);
+        }
+    }
+    let programs = read_programs(&args.programs);
 
     let mut input_file = File::open(input_path).expect("Failed to open input binary");
     let mut input_data = Vec::new();
     input_file.read_to_end(&mut input_data).expect("Failed to read input binary");
 
-    if  !validate_elf(&input_data) {
-        eprintln!("Error: Input file is not a valid ELF binary");
+    if input_data.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        eprintln!("Error: Input file too large ({} bytes, at most {})", input_data.len(), MAX_DECOMPRESSED_SIZE);
         std::process::exit(1);
     }
+    let overridden = match (args.interpreter, bundled_interpreter) {
+        (Some(path), _) => Some(Interpreter::System(path)),
+        (None, Some(name)) => Some(Interpreter::Bundled(name)),
+        (None, None) => None,
+    };
+    if validate_elf(&input_data) {
+        if overridden.is_some() || args.interpreter_arg.is_some() {
+            eprintln!("Error: --interpreter, --bundle-interpreter and --interpreter-arg are for scripts, not ELF binaries");
+            std::process::exit(1);
+        }
+    } else {
+        let script = match (overridden, Script::from_shebang(&input_data)) {
+            (Some(interpreter), _) => Script { interpreter, arg: args.interpreter_arg },
+            (None, Some(shebang)) => Script { arg: args.interpreter_arg.or(shebang.arg), ..shebang },
+            (None, None) => {
+                eprintln!("Error: Input file is neither a valid ELF binary nor a script with a #! line (see --interpreter)");
+                std::process::exit(1);
+            }
+        };
+        config.script = Some(script);
+    }
 
-    if input_data.len() < 100 {
+    // Padding would end up in a script
+    if input_data.len() < 100 && config.script.is_none() {
         eprintln!("Warning: Input file is very small, ensuring minimum size");
         // Pad to ensure minimum size
         while input_data.len() < 100 {
@@ -51,7 +667,36 @@ fn main() {
     let aes_key = AES128::generate_key();
     let aes_key_hash = compute_blake3(&aes_key);
 
-    let aes_key_bits: Vec<i64> = aes_key.iter()
+    // A machine-bound binary hides another key in the whitebox, and the payload key
+    // can only be recovered with that key and the fingerprint of an allowed host
+    let whitebox_key = if hosts.is_empty() {
+        aes_key
+    } else {
+        println!("[*] Binding to {} host(s)...", hosts.len());
+        let whitebox_key = AES128::generate_key();
+        config.host_keys = hosts
+            .iter()
+            .map(|host| {
+                let mask = host_key_mask(&whitebox_key, &host.digest());
+                std::array::from_fn(|i| aes_key[i] ^ mask[i])
+            })
+            .collect();
+        whitebox_key
+    };
+    // With a passphrase, the whitebox only holds one share of that key
+    let whitebox_key = match &args.passphrase_from {
+        Some(source) => {
+            println!("[*] Deriving the passphrase share...");
+            let passphrase = read_passphrase();
+            let (settings, share) = Passphrase::new(passphrase.as_bytes(), source.clone(), rand::random(), Cost::default())
+                .expect("Failed to derive the passphrase share");
+            config.passphrase = Some(settings);
+            std::array::from_fn(|i| whitebox_key[i] ^ share[i])
+        }
+        None => whitebox_key,
+    };
+
+    let aes_key_bits: Vec<i64> = whitebox_key.iter()
         .flat_map(|&byte| (0..8).map(move |i| ((byte >> i) & 1) as i64))
         .collect();
     let mut message_padded = vec![0i64; pub_enc_data.degree];
@@ -59,12 +704,28 @@ fn main() {
     let message_array = Array1::from_vec(message_padded);
 
     println!("[*] Encrypting AES key with white-box...");
-    let (a1, a2) = encrypt_func(&message_array, &pub_enc_data.pka, &pub_enc_data.pkb, pub_enc_data.degree, pub_enc_data.modulus);
+    // The noise occasionally grows too large to decrypt, and the stub would then fail
+    // with a wrong key every time: check the ciphertext and draw new noise if needed
+    let context = DecryptContext::new(&white_data, pub_enc_data.degree, pub_enc_data.modulus).expect("Invalid white-box data");
+    let encrypted_key = std::iter::repeat_with(|| {
+        encrypt_func(&message_array, &pub_enc_data.pka, &pub_enc_data.pkb, pub_enc_data.degree, pub_enc_data.modulus)
+    })
+    .take(16)
+    .find(|(a1, a2)| context.decrypt(a1, a2).as_ref() == Some(&message_array));
+    let Some((a1, a2)) = encrypted_key else {
+        eprintln!("Error: the white-box could not decrypt the encrypted AES key in 16 attempts");
+        std::process::exit(1);
+    };
 
     // Serialize whitebox data and encrypted key
     let serialized_white_data = bincode::serialize(&white_data).expect("Failed to serialize WhiteData");
     let serialized_a1 = bincode::serialize(&a1).expect("Failed to serialize a1");
     let serialized_a2 = bincode::serialize(&a2).expect("Failed to serialize a2");
+    let serialized_config = bincode::serialize(&config).expect("Failed to serialize Config");
+    if serialized_config.len() as u64 > MAX_CONFIG_SIZE {
+        eprintln!("Error: configuration too large ({} bytes, at most {}); shorten the license", serialized_config.len(), MAX_CONFIG_SIZE);
+        std::process::exit(1);
+    }
 
     println!("[*] Compressing input binary...");
     let compressed_data = compress(&input_data);
@@ -73,27 +734,36 @@ fn main() {
     let compressed_hash = compute_blake3(&compressed_data);
 
     println!("[*] Encrypting compressed data...");
-    let aes = AES128::new(&aes_key);
-    let padded_data = aes::pad_pkcs7(&compressed_data, 16);
-    let encrypted_data = (aes.encrypt)(&aes, &padded_data);
+    let encrypted_data = encrypt_payload(&compressed_data, &aes_key);
     let encrypted_size = encrypted_data.len() as u64;
+    let encrypted_decoy = encrypt_decoy(&decoy);
+    let encrypted_resources = encrypt_resources(&bundled, &aes_key);
+    let encrypted_programs = encrypt_programs(&programs, &aes_key);
     let decompressed_size = input_data.len() as u64;
 
     println!("[*] Generating the packed binary...");
-    let mut packed_data = STUB_DATA.to_vec();
-    
     // Organize data sections consistently
-    // Format: [STUB] [encrypted_data] [a1] [a2] [white_data] [sizes] [checksums] [final_checksum]
+    // Format: [encrypted_data] [a1] [a2] [white_data] [config] [decoy] [resources] [programs] [sizes] [checksums] [signature],
+    // stored in an ELF note appended to the stub
+    let mut packed_data = Vec::new();
     packed_data.extend_from_slice(&encrypted_data);
     packed_data.extend_from_slice(&serialized_a1);
     packed_data.extend_from_slice(&serialized_a2);
     packed_data.extend_from_slice(&serialized_white_data);
+    packed_data.extend_from_slice(&serialized_config);
+    packed_data.extend_from_slice(&encrypted_decoy);
+    packed_data.extend_from_slice(&encrypted_resources);
+    packed_data.extend_from_slice(&encrypted_programs);
     
     // Add size fields
     packed_data.extend_from_slice(&encrypted_size.to_le_bytes());
     packed_data.extend_from_slice(&(serialized_a1.len() as u64).to_le_bytes());
     packed_data.extend_from_slice(&(serialized_a2.len() as u64).to_le_bytes());
     packed_data.extend_from_slice(&(serialized_white_data.len() as u64).to_le_bytes());
+    packed_data.extend_from_slice(&(serialized_config.len() as u64).to_le_bytes());
+    packed_data.extend_from_slice(&(encrypted_decoy.len() as u64).to_le_bytes());
+    packed_data.extend_from_slice(&(encrypted_resources.len() as u64).to_le_bytes());
+    packed_data.extend_from_slice(&(encrypted_programs.len() as u64).to_le_bytes());
     packed_data.extend_from_slice(&decompressed_size.to_le_bytes());
     
     // Add checksums - now with Blake3 only
@@ -101,17 +771,17 @@ fn main() {
     packed_data.extend_from_slice(&compressed_hash);
     packed_data.extend_from_slice(&aes_key_hash);
     
-    // Compute final checksum for the entire packed binary (except this checksum itself)
-    let final_hash = compute_blake3(&packed_data);
-    packed_data.extend_from_slice(&final_hash);
-
-    // Ensure minimum size for the packed binary
-    if packed_data.len() < 1024 {
-        eprintln!("Warning: Packed binary is small, ensuring minimum size");
-        while packed_data.len() < 1024 {
-            packed_data.push(0); // Padding to ensure minimum size
+    // Room for the signature, which covers the stub segments as well
+    packed_data.extend_from_slice(&[0; SIGNATURE_SIZE]);
+    let (mut packed_data, data_range) = match embed(&stub, &packed_data) {
+        Ok(packed) => packed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
         }
-    }
+    };
+    let digest = find_pack_in_file(&packed_data).expect("Failed to find the embedded pack data").digest;
+    packed_data[data_range.end - SIGNATURE_SIZE..data_range.end].copy_from_slice(&signature::sign(&secret_key, &digest));
 
     let mut output_file = File::create(output_path).expect("Failed to create output file");
     output_file.write_all(&packed_data).expect("Failed to write packed binary");
@@ -122,7 +792,7 @@ fn main() {
 
     println!("Packed binary created at {}", output_path);
     println!("Original checksum: {}", hash_to_hex(&original_hash));
-    println!("Final checksum: {}", hash_to_hex(&final_hash));
+    println!("Signed with public key: {}", hash_to_hex(&public_key));
 }
 
 #[cfg(test)]
@@ -139,4 +809,26 @@ mod tests {
         assert!(!validate_elf(b"Not an ELF file"));
         assert!(!validate_elf(b"\x7FEL"));
     }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex::<2>("0aFf\n"), Some([0x0a, 0xff]));
+        assert_eq!(parse_hex::<2>("0aF"), None);
+        assert_eq!(parse_hex::<2>("0aFg"), None);
+        assert_eq!(parse_hex::<2>("é0a"), None);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2000-03-01"), Ok(951_868_800));
+        assert_eq!(parse_date("2024-02-29"), Ok(1_709_164_800));
+        assert_eq!(parse_date("1700000000"), Ok(1_700_000_000));
+        assert_eq!(parse_end_date("2024-02-29"), Ok(1_709_251_200));
+        assert_eq!(parse_end_date("1700000000"), Ok(1_700_000_000));
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("24-01-01").is_err());
+        assert!(parse_date("tomorrow").is_err());
+    }
 }
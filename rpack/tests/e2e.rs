@@ -0,0 +1,700 @@
+// End-to-end tests: build the sample programs from tests/, pack them and check that
+// the packed binary behaves exactly like the original one.
+// Anti-VM checks are disabled at pack time so these can run on CI virtual machines.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const RPACK: &str = env!("CARGO_BIN_EXE_rpack");
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests").join(name)
+}
+
+fn work_dir(test: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("e2e").join(test);
+    fs::create_dir_all(&dir).expect("Failed to create work directory");
+    dir
+}
+
+// Compile a fixture, or return None if the compiler is not installed.
+fn compile(compiler: &str, args: &[&str], output: &Path) -> Option<PathBuf> {
+    let status = match Command::new(compiler).args(args).status() {
+        Ok(status) => status,
+        Err(_) => {
+            eprintln!("skipping: {} not found", compiler);
+            return None;
+        }
+    };
+    assert!(status.success(), "{} failed to build the fixture", compiler);
+    Some(output.to_path_buf())
+}
+
+fn pack(input: &Path) -> PathBuf {
+    pack_with(input, &[], "packed")
+}
+
+fn pack_with(input: &Path, options: &[&str], extension: &str) -> PathBuf {
+    let output = input.with_extension(extension);
+    let result = Command::new(RPACK)
+        .env_remove("RPACK_SIGNING_KEY")
+        .args(["--no-anti-vm", "--ephemeral-key"])
+        .args(options)
+        .arg(input)
+        .arg(&output)
+        .output()
+        .expect("Failed to run rpack");
+    assert!(result.status.success(), "rpack failed: {}", String::from_utf8_lossy(&result.stderr));
+    output
+}
+
+fn run(program: &Path, args: &[&str], envs: &[(&str, &str)], stdin: &[u8], cwd: &Path) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(envs.iter().copied())
+        .current_dir(cwd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start program");
+    child.stdin.take().unwrap().write_all(stdin).expect("Failed to write stdin");
+    child.wait_with_output().expect("Failed to wait for program")
+}
+
+fn assert_same_output(original: &Output, packed: &Output) {
+    assert_eq!(String::from_utf8_lossy(&packed.stdout), String::from_utf8_lossy(&original.stdout));
+    assert_eq!(String::from_utf8_lossy(&packed.stderr), String::from_utf8_lossy(&original.stderr));
+    assert_eq!(packed.status.code(), original.status.code());
+}
+
+#[test]
+fn test_hello_c_reads_stdin() {
+    let dir = work_dir("hello_c");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let packed = pack(&binary);
+
+    let original = run(&binary, &[], &[], b"bob\n", &dir);
+    assert_eq!(original.stdout, b"Enter your name: Hello bob\n");
+    assert_same_output(&original, &run(&packed, &[], &[], b"bob\n", &dir));
+}
+
+// Whether `tmp` can be mounted on /tmp in a private mount namespace (needs root and unshare)
+fn can_mount_private_tmp(tmp: &Path) -> bool {
+    let probe = Command::new("unshare").args(["-m", "mount", "--bind", tmp.to_str().unwrap(), "/tmp"]).output();
+    probe.is_ok_and(|output| output.status.success())
+}
+
+// Run `program` in a private mount namespace where `tmp` is mounted on /tmp
+fn run_with_private_tmp(program: &Path, tmp: &Path, cwd: &Path) -> Output {
+    let script = "mount --bind \"$1\" /tmp && exec \"$0\"";
+    run(Path::new("unshare"), &["-m", "sh", "-c", script, program.to_str().unwrap(), tmp.to_str().unwrap()], &[], b"", cwd)
+}
+
+#[test]
+fn test_files_cpp_writes_file() {
+    let dir = work_dir("files_cpp");
+    let source = fixture("files.cpp");
+    let binary = dir.join("files");
+    let Some(binary) = compile("g++", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let packed = pack(&binary);
+
+    // The fixture always writes /tmp/test.txt. Give it this test's own /tmp where mount
+    // namespaces are available, so nothing else on the machine sees or clobbers the file.
+    let tmp = dir.join("tmp");
+    fs::create_dir_all(&tmp).unwrap();
+    let private = can_mount_private_tmp(&tmp);
+    let written = if private { tmp.join("test.txt") } else { PathBuf::from("/tmp/test.txt") };
+    let run_files = |program: &Path| {
+        if private {
+            run_with_private_tmp(program, &tmp, &dir)
+        } else {
+            run(program, &[], &[], b"", &dir)
+        }
+    };
+
+    let _ = fs::remove_file(&written);
+    let original = run_files(&binary);
+    let original_file = fs::read(&written).expect("Original program did not write its file");
+    fs::remove_file(&written).unwrap();
+
+    let packed_run = run_files(&packed);
+    let packed_file = fs::read(&written).expect("Packed program did not write its file");
+    fs::remove_file(&written).unwrap();
+
+    assert_eq!(original_file, b"Hello, World!");
+    assert_eq!(packed_file, original_file);
+    assert_same_output(&original, &packed_run);
+}
+
+#[test]
+fn test_hello_go_reexecs_itself() {
+    let dir = work_dir("hello_go");
+    let source = fixture("hello.go");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("go", &["build", "-o", binary.to_str().unwrap(), source.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let packed = pack(&binary);
+
+    let original = run(&binary, &[], &[], b"", &dir);
+    assert_eq!(String::from_utf8_lossy(&original.stdout), (1..=10).map(|i| format!("{}\n", i)).collect::<String>());
+    assert_same_output(&original, &run(&packed, &[], &[], b"", &dir));
+}
+
+#[test]
+fn test_args_env_and_exit_code() {
+    let dir = work_dir("sh");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let binary = dir.join("sh");
+    fs::copy("/bin/sh", &binary).unwrap();
+    let packed = pack(&binary);
+
+    let args = ["-c", "read line; echo \"$0 $1 $RPACK_E2E $line\"; echo err >&2; exit 3", "zero", "one"];
+    let envs = [("RPACK_E2E", "env")];
+    let original = run(&binary, &args, &envs, b"stdin\n", &dir);
+    assert_eq!(original.stdout, b"zero one env stdin\n");
+    assert_eq!(original.status.code(), Some(3));
+    assert_same_output(&original, &run(&packed, &args, &envs, b"stdin\n", &dir));
+}
+
+#[test]
+fn test_busy_machine() {
+    // A large payload takes a while to unpack, during which busy threads keep every CPU
+    // taken: waiting for a CPU is not a debugger stopping the stub
+    let dir = work_dir("busy");
+    let binary = dir.join("rpack");
+    fs::copy(RPACK, &binary).unwrap();
+    let packed = pack(&binary);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    let spinners: Vec<_> = (0..4 * cpus)
+        .map(|_| {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || while !stop.load(Ordering::Relaxed) {})
+        })
+        .collect();
+    let outputs: Vec<_> = (0..5).map(|_| run(&packed, &["keygen", "--help"], &[], b"", &dir)).collect();
+    stop.store(true, Ordering::Relaxed);
+    for spinner in spinners {
+        spinner.join().unwrap();
+    }
+    for output in &outputs {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(output.stdout.starts_with(b"Generate an Ed25519 signing key"));
+    }
+}
+
+// Run `program` in a private mount namespace where /proc is an empty tmpfs.
+// Returns None if namespaces are not available (not root, no unshare).
+fn run_without_proc(program: &Path, stdin: &[u8], cwd: &Path) -> Option<Output> {
+    let probe = Command::new("unshare").args(["-m", "mount", "-t", "tmpfs", "none", "/proc"]).output();
+    if !probe.is_ok_and(|output| output.status.success()) {
+        eprintln!("skipping: cannot hide /proc in a mount namespace");
+        return None;
+    }
+    let script = "mount -t tmpfs none /proc && exec \"$0\"";
+    Some(run(Path::new("unshare"), &["-m", "sh", "-c", script, program.to_str().unwrap()], &[], stdin, cwd))
+}
+
+#[test]
+fn test_missing_proc_policy() {
+    let dir = work_dir("no_proc");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let allowed = pack_with(&binary, &["--allow-missing-proc"], "allowed");
+    let strict = pack(&binary);
+
+    let Some(allowed_run) = run_without_proc(&allowed, b"bob\n", &dir) else {
+        return;
+    };
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &allowed_run);
+
+    let strict_run = run_without_proc(&strict, b"bob\n", &dir).unwrap();
+    assert!(!strict_run.status.success());
+    assert!(strict_run.stdout.is_empty());
+}
+
+fn rpack(args: &[&str], envs: &[(&str, &str)]) -> Output {
+    Command::new(RPACK).args(args).env_remove("RPACK_SIGNING_KEY").envs(envs.iter().copied()).output().expect("Failed to run rpack")
+}
+
+#[test]
+fn test_signature() {
+    let dir = work_dir("signature");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+
+    let key = dir.join("signing.key");
+    let _ = fs::remove_file(&key);
+    let keygen = rpack(&["keygen", key.to_str().unwrap()], &[]);
+    assert!(keygen.status.success());
+    let stdout = String::from_utf8(keygen.stdout).unwrap();
+    let public_key = stdout.lines().find_map(|line| line.strip_prefix("Public key: ")).unwrap().to_string();
+
+    // Packing needs a key, or an explicit opt-in to a throwaway one
+    let packed = dir.join("hello.packed");
+    let unsigned = rpack(&["--no-anti-vm", binary.to_str().unwrap(), packed.to_str().unwrap()], &[]);
+    assert!(!unsigned.status.success());
+    assert!(String::from_utf8_lossy(&unsigned.stderr).contains("rpack keygen"));
+
+    let result = rpack(
+        &["--no-anti-vm", binary.to_str().unwrap(), packed.to_str().unwrap()],
+        &[("RPACK_SIGNING_KEY", key.to_str().unwrap())],
+    );
+    assert!(result.status.success(), "rpack failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(rpack(&["verify", packed.to_str().unwrap(), "--public-key", &public_key], &[]).status.success());
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&packed, &[], &[], b"bob\n", &dir));
+
+    // Signed with another key
+    let other_key = "00".repeat(32);
+    assert!(!rpack(&["verify", packed.to_str().unwrap(), "--public-key", &other_key], &[]).status.success());
+
+    // Any modification breaks the signature
+    let mut image = fs::read(&packed).unwrap();
+    let middle = image.len() / 2;
+    image[middle] ^= 1;
+    let tampered = dir.join("hello.tampered");
+    fs::write(&tampered, &image).unwrap();
+    fs::set_permissions(&tampered, fs::metadata(&packed).unwrap().permissions()).unwrap();
+    assert!(!rpack(&["verify", tampered.to_str().unwrap()], &[]).status.success());
+    let tampered_run = run(&tampered, &[], &[], b"bob\n", &dir);
+    assert!(!tampered_run.status.success());
+    assert!(tampered_run.stdout.is_empty());
+}
+
+#[test]
+fn test_detection_reactions() {
+    let dir = work_dir("reactions");
+    let binary = dir.join("hello");
+    let decoy = dir.join("decoy");
+    let decoy_source = dir.join("decoy.c");
+    fs::write(&decoy_source, "#include <stdio.h>\nint main(void) { puts(\"rpack-e2e-decoy\"); return 9; }\n").unwrap();
+    let source = fixture("hello.c");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let decoy = compile("gcc", &["-static", decoy_source.to_str().unwrap(), "-o", decoy.to_str().unwrap()], &decoy).unwrap();
+
+    // Without /proc (and without --allow-missing-proc) the tracer check reports tampering
+    let exit = pack_with(&binary, &["--on-detection", "exit:7"], "exit");
+    let Some(exit_run) = run_without_proc(&exit, b"bob\n", &dir) else {
+        return;
+    };
+    assert_eq!(exit_run.status.code(), Some(7));
+    assert!(exit_run.stdout.is_empty());
+    // Production stubs do not explain themselves
+    assert!(exit_run.stderr.is_empty());
+
+    let custom = pack_with(&binary, &["--decoy", decoy.to_str().unwrap()], "decoy");
+    let custom_run = run_without_proc(&custom, b"bob\n", &dir).unwrap();
+    assert_eq!(custom_run.stdout, b"rpack-e2e-decoy\n");
+    assert_eq!(custom_run.status.code(), Some(9));
+
+    let log = pack_with(&binary, &["--on-detection", "log"], "log");
+    let log_run = run_without_proc(&log, b"bob\n", &dir).unwrap();
+    assert_eq!(log_run.stdout, b"Enter your name: Hello bob\n");
+    assert!(String::from_utf8_lossy(&log_run.stderr).contains("tampering detected"));
+
+    // The decoy is encrypted in the packed file
+    let packed_bytes = fs::read(&custom).unwrap();
+    assert!(!packed_bytes.windows(15).any(|window| window == b"rpack-e2e-decoy"));
+}
+
+#[test]
+fn test_diagnostics_stub() {
+    let dir = work_dir("diagnostics");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let packed = pack_with(&binary, &["--diagnostics", "--on-detection", "exit:7"], "diagnostics");
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&packed, &[], &[], b"bob\n", &dir));
+
+    let Some(no_proc_run) = run_without_proc(&packed, b"bob\n", &dir) else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&no_proc_run.stderr);
+    assert!(stderr.contains("debugger check 'tracer' fired"), "unexpected stderr: {}", stderr);
+    assert!(stderr.contains("error E107"), "unexpected stderr: {}", stderr);
+    assert!(stderr.contains("reacting with exit:7"), "unexpected stderr: {}", stderr);
+    assert_eq!(no_proc_run.status.code(), Some(7));
+}
+
+#[test]
+fn test_machine_binding() {
+    let dir = work_dir("binding");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let here = dir.join("here.json");
+    assert!(rpack(&["fingerprint", "-o", here.to_str().unwrap()], &[]).status.success());
+    let mut fingerprint: serde_json::Value = serde_json::from_str(&fs::read_to_string(&here).unwrap()).unwrap();
+    fingerprint["machine_id"] = "another machine".into();
+    let elsewhere = dir.join("elsewhere.json");
+    fs::write(&elsewhere, fingerprint.to_string()).unwrap();
+
+    let bound = pack_with(&binary, &["--bind-to", elsewhere.to_str().unwrap(), "--bind-to", here.to_str().unwrap()], "bound");
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&bound, &[], &[], b"bob\n", &dir));
+
+    let foreign = pack_with(&binary, &["--bind-to", elsewhere.to_str().unwrap(), "--on-detection", "exit:5"], "foreign");
+    let foreign_run = run(&foreign, &[], &[], b"bob\n", &dir);
+    assert_eq!(foreign_run.status.code(), Some(5));
+    assert!(foreign_run.stdout.is_empty());
+}
+
+#[test]
+fn test_license() {
+    let dir = work_dir("license");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let binary = dir.join("sh");
+    fs::copy("/bin/sh", &binary).unwrap();
+    let args = ["-c", "test -n \"$RPACK_LICENSE\" && echo licensed"];
+
+    // Only the stub sets the variable; an inherited one is replaced
+    let licensed = pack_with(&binary, &["--not-after", "2999-12-31", "--feature", "pro"], "licensed");
+    let output = run(&licensed, &args, &[("RPACK_LICENSE", "")], b"", &dir);
+    assert_eq!(output.stdout, b"licensed\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let expired = pack_with(&binary, &["--not-after", "2000-01-01", "--license-message", "rpack-e2e expired"], "expired");
+    let output = run(&expired, &args, &[], b"", &dir);
+    assert_eq!(output.stdout, b"");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "rpack-e2e expired\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let early = pack_with(&binary, &["--not-before", "2999-01-01"], "early");
+    assert_eq!(run(&early, &args, &[], b"", &dir).status.code(), Some(1));
+}
+
+#[test]
+fn test_passphrase() {
+    let dir = work_dir("passphrase");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let binary = dir.join("sh");
+    fs::copy("/bin/sh", &binary).unwrap();
+    let here = dir.join("here.json");
+    assert!(rpack(&["fingerprint", "-o", here.to_str().unwrap()], &[]).status.success());
+
+    // The passphrase share sits on top of the host binding
+    let packed = dir.join("sh.passphrase");
+    let result = rpack(
+        &["--no-anti-vm", "--ephemeral-key", "--passphrase-from", "env:RPACK_E2E_PASS", "--bind-to", here.to_str().unwrap(), binary.to_str().unwrap(), packed.to_str().unwrap()],
+        &[("RPACK_PASSPHRASE", "correct horse")],
+    );
+    assert!(result.status.success(), "rpack failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let args = ["-c", "echo \"ran ${RPACK_E2E_PASS-without the passphrase}\""];
+    let output = run(&packed, &args, &[("RPACK_E2E_PASS", "correct horse")], b"", &dir);
+    assert_eq!(output.stdout, b"ran without the passphrase\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&packed, &args, &[("RPACK_E2E_PASS", "battery staple")], b"", &dir);
+    assert_eq!(output.stdout, b"");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Wrong passphrase\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(run(&packed, &args, &[], b"", &dir).status.code(), Some(1));
+}
+
+#[test]
+fn test_survives_binary_tools() {
+    let dir = work_dir("tools");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let packed = pack(&binary);
+    let original = run(&binary, &[], &[], b"bob\n", &dir);
+
+    let appended = dir.join("hello.appended");
+    fs::write(&appended, [fs::read(&packed).unwrap(), b"trailing signature".to_vec()].concat()).unwrap();
+    fs::set_permissions(&appended, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_same_output(&original, &run(&appended, &[], &[], b"bob\n", &dir));
+
+    let stripped = dir.join("hello.strip");
+    let copied = dir.join("hello.objcopy");
+    let (packed_arg, stripped_arg, copied_arg) = (packed.to_str().unwrap(), stripped.to_str().unwrap(), copied.to_str().unwrap());
+    for (tool, args, output) in [
+        ("strip", vec!["-o", stripped_arg, packed_arg], &stripped),
+        ("objcopy", vec!["--add-section", ".extra=/dev/null", packed_arg, copied_arg], &copied),
+    ] {
+        match Command::new(tool).args(args).status() {
+            Ok(status) if status.success() => {}
+            _ => {
+                eprintln!("skipping {}: not installed or failed", tool);
+                continue;
+            }
+        }
+        assert_same_output(&original, &run(output, &[], &[], b"bob\n", &dir));
+        assert!(rpack(&["verify", output.to_str().unwrap()], &[]).status.success(), "{} broke the signature", tool);
+    }
+}
+
+#[test]
+fn test_text_patched_in_memory() {
+    let dir = work_dir("patch_text");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let library = dir.join("patch_text.so");
+    let patch = fixture("patch_text.c");
+    compile("gcc", &["-shared", "-fPIC", patch.to_str().unwrap(), "-o", library.to_str().unwrap()], &library).unwrap();
+    let packed = pack_with(&binary, &["--diagnostics", "--on-detection", "exit:7"], "packed");
+
+    // The file and its signature are intact: only the code in memory changed
+    let output = run(&packed, &[], &[("LD_PRELOAD", library.to_str().unwrap())], b"bob\n", &dir);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error E105"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.status.code(), Some(7));
+    assert_eq!(run(&packed, &[], &[], b"bob\n", &dir).status.code(), Some(0));
+}
+
+#[test]
+fn test_debugger_detection() {
+    let dir = work_dir("debugger");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let tracer = dir.join("tracer");
+    let tracer_source = fixture("tracer.c");
+    compile("gcc", &[tracer_source.to_str().unwrap(), "-o", tracer.to_str().unwrap()], &tracer).unwrap();
+    let packed = pack_with(&binary, &["--diagnostics", "--on-detection", "exit:7"], "packed");
+
+    let output = run(&tracer, &[packed.to_str().unwrap()], &[], b"bob\n", &dir);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("debugger check 'tracer' fired"), "unexpected stderr: {}", stderr);
+    assert!(stderr.contains("debugger check 'SIGTRAP handler' fired"), "unexpected stderr: {}", stderr);
+    assert!(stderr.contains("error E107"), "unexpected stderr: {}", stderr);
+    assert_eq!(output.status.code(), Some(7));
+}
+
+#[test]
+fn test_debugger_checks_without_debugger() {
+    let dir = work_dir("no_debugger");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let sandbox = dir.join("noptrace");
+    let sandbox_source = fixture("noptrace.c");
+    compile("gcc", &[sandbox_source.to_str().unwrap(), "-o", sandbox.to_str().unwrap()], &sandbox).unwrap();
+    let library = dir.join("preload.so");
+    let library_source = fixture("preload.c");
+    compile("gcc", &["-shared", "-fPIC", library_source.to_str().unwrap(), "-o", library.to_str().unwrap()], &library).unwrap();
+    let packed = pack_with(&binary, &["--diagnostics", "--on-detection", "exit:7"], "packed");
+    let unchecked = pack_with(&binary, &["--diagnostics", "--no-anti-debugger", "--on-detection", "exit:7"], "unchecked");
+
+    // A preloaded library where ptrace is denied: both weak checks fire, but no debugger
+    let envs = [("LD_PRELOAD", library.to_str().unwrap())];
+    let original = run(&sandbox, &[binary.to_str().unwrap()], &envs, b"bob\n", &dir);
+    let output = run(&sandbox, &[packed.to_str().unwrap()], &envs, b"bob\n", &dir);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("debugger check 'PTRACE_TRACEME' fired"), "unexpected stderr: {}", stderr);
+    assert!(stderr.contains("debugger check 'LD_PRELOAD/LD_AUDIT' fired"), "unexpected stderr: {}", stderr);
+    assert_eq!(output.stdout, original.stdout);
+    assert_eq!(output.status.code(), original.status.code());
+
+    let output = run(&sandbox, &[unchecked.to_str().unwrap()], &envs, b"bob\n", &dir);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("debugger check"));
+    assert_eq!(output.stdout, original.stdout);
+}
+
+#[test]
+fn test_guardian() {
+    let dir = work_dir("guardian");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let binary = dir.join("sh");
+    fs::copy("/bin/sh", &binary).unwrap();
+    let packed = pack_with(&binary, &["--guardian"], "guarded");
+
+    // The stub process stays behind as the payload's parent and tracer
+    let script = "read line; echo \"$line\"; test \"$(sed -n 's/^TracerPid:\\t//p' /proc/$$/status)\" = \"$PPID\" && echo traced; exit 3";
+    let output = run(&packed, &["-c", script], &[], b"bob\n", &dir);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bob\ntraced\n");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = run(&packed, &["-c", "kill -TERM $$"], &[], b"", &dir);
+    // Killed by SIGTERM, like the payload
+    assert_eq!(output.status.signal(), Some(15));
+}
+
+#[test]
+fn test_trusted_environment() {
+    let dir = work_dir("trusted_environment");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let environment = dir.join("environment.json");
+    assert!(rpack(&["environment", "-o", environment.to_str().unwrap()], &[]).status.success());
+
+    // With the anti-VM checks on, even where they would fire (CI virtual machines)
+    let packed = dir.join("hello.trusted");
+    let result = rpack(
+        &["--ephemeral-key", "--trust-env", environment.to_str().unwrap(), "--on-detection", "exit:7", binary.to_str().unwrap(), packed.to_str().unwrap()],
+        &[],
+    );
+    assert!(result.status.success(), "rpack failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&packed, &[], &[], b"bob\n", &dir));
+
+    // An entry that names nothing would trust every machine
+    fs::write(&environment, "{}").unwrap();
+    let result = rpack(&["--ephemeral-key", "--trust-env", environment.to_str().unwrap(), binary.to_str().unwrap(), packed.to_str().unwrap()], &[]);
+    assert!(!result.status.success());
+}
+
+#[test]
+fn test_audit() {
+    let dir = work_dir("audit");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    let tracer = dir.join("tracer");
+    let tracer_source = fixture("tracer.c");
+    compile("gcc", &[tracer_source.to_str().unwrap(), "-o", tracer.to_str().unwrap()], &tracer).unwrap();
+    let log = dir.join("audit.log");
+    let _ = fs::remove_file(&log);
+    let target = format!("file:{}", log.display());
+    let packed = pack_with(&binary, &["--audit", &target, "--on-detection", "exit:7"], "packed");
+
+    // Detections are recorded, not reacted to
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&packed, &[], &[], b"bob\n", &dir));
+    let traced = run(&tracer, &[packed.to_str().unwrap()], &[], b"bob\n", &dir);
+    assert!(traced.status.success(), "stderr: {}", String::from_utf8_lossy(&traced.stderr));
+
+    let result = rpack(&["audit-summary", log.to_str().unwrap()], &[]);
+    assert!(result.status.success(), "rpack failed: {}", String::from_utf8_lossy(&result.stderr));
+    let summary = String::from_utf8_lossy(&result.stdout);
+    assert!(summary.starts_with("2 run(s)"), "unexpected summary: {}", summary);
+    assert!(summary.contains("E107 debugger"), "unexpected summary: {}", summary);
+    // --no-anti-vm does not stop an audit from running the VM checks
+    assert!(summary.contains("VM hypervisor flag"), "unexpected summary: {}", summary);
+}
+
+#[test]
+fn test_resources() {
+    let dir = work_dir("resources");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let binary = dir.join("sh");
+    fs::copy("/bin/sh", &binary).unwrap();
+    let config = dir.join("app.conf");
+    fs::write(&config, "threads = 4\n").unwrap();
+    let model = dir.join("model.bin");
+    fs::write(&model, vec![7u8; 1 << 20]).unwrap();
+    let config_option = format!("settings.toml={}", config.display());
+    let packed = pack_with(&binary, &["--resource", &config_option, "--resource", model.to_str().unwrap()], "bundled");
+    // Encrypted in the packed binary
+    let packed_data = fs::read(&packed).unwrap();
+    assert!(!packed_data.windows(12).any(|window| window == b"threads = 4\n"));
+
+    let script = "echo \"$RPACK_RESOURCES\" | tr ':' '\\n' | cut -d= -f1; \
+        for entry in $(echo \"$RPACK_RESOURCES\" | tr ':' ' '); do wc -c < /proc/self/fd/${entry#*=}; done; \
+        cat /proc/self/fd/$(echo \"$RPACK_RESOURCES\" | sed 's/.*settings.toml=\\([0-9]*\\).*/\\1/')";
+    let output = run(&packed, &["-c", script], &[("RPACK_RESOURCES", "forged=0")], b"", &dir);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "settings.toml\nmodel.bin\n12\n1048576\nthreads = 4\n");
+
+    let result = rpack(&["--ephemeral-key", "--resource", "a:b=/etc/hostname", binary.to_str().unwrap(), dir.join("invalid").to_str().unwrap()], &[]);
+    assert!(!result.status.success());
+}
+
+#[test]
+fn test_multi_call_bundle() {
+    let dir = work_dir("bundle");
+    let source = fixture("hello.c");
+    let binary = dir.join("hello");
+    let Some(binary) = compile("gcc", &[source.to_str().unwrap(), "-o", binary.to_str().unwrap()], &binary) else {
+        return;
+    };
+    if !Path::new("/bin/echo").exists() {
+        eprintln!("skipping: /bin/echo not found");
+        return;
+    }
+    let packed = pack_with(&binary, &["--program", "say=/bin/echo", "--program", binary.to_str().unwrap()], "bundle");
+
+    // Selected by argv[0], as through a symlink
+    let link = dir.join("say");
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink(&packed, &link).unwrap();
+    assert_eq!(run(&link, &["a", "b"], &[], b"", &dir).stdout, b"a b\n");
+    // Or by the first argument, which then takes argv[0]'s place
+    assert_eq!(run(&packed, &["say", "-n", "c"], &[], b"", &dir).stdout, b"c");
+    // The main program otherwise
+    assert_same_output(&run(&binary, &["x"], &[], b"bob\n", &dir), &run(&packed, &["x"], &[], b"bob\n", &dir));
+    assert_same_output(&run(&binary, &[], &[], b"bob\n", &dir), &run(&packed, &["hello"], &[], b"bob\n", &dir));
+
+    let result = rpack(&["--ephemeral-key", "--program", "a/b=/bin/echo", binary.to_str().unwrap(), dir.join("invalid").to_str().unwrap()], &[]);
+    assert!(!result.status.success());
+}
+
+#[test]
+fn test_script() {
+    let dir = work_dir("script");
+    if !Path::new("/bin/sh").exists() {
+        eprintln!("skipping: /bin/sh not found");
+        return;
+    }
+    let script = dir.join("greet.sh");
+    fs::copy(fixture("greet.sh"), &script).unwrap();
+    let original = run(Path::new("/bin/sh"), &[script.to_str().unwrap(), "a", "b c"], &[], b"bob\n", &dir);
+    assert_eq!(original.status.code(), Some(3));
+
+    // With the interpreter of its #! line
+    let packed = pack_with(&script, &[], "packed");
+    assert!(!fs::read(&packed).unwrap().windows(11).any(|window| window == b"hello $name"));
+    assert_same_output(&original, &run(&packed, &["a", "b c"], &[], b"bob\n", &dir));
+    // With an interpreter packed along, and the guardian
+    let bundled = pack_with(&script, &["--bundle-interpreter", "sh=/bin/sh", "--interpreter-arg", "-e", "--guardian"], "bundled");
+    assert_same_output(&original, &run(&bundled, &["a", "b c"], &[], b"bob\n", &dir));
+
+    let missing = pack_with(&script, &["--interpreter", "/nonexistent/sh", "--on-detection", "exit"], "missing");
+    assert!(!run(&missing, &[], &[], b"", &dir).status.success());
+    let plain = dir.join("plain.txt");
+    fs::write(&plain, "echo hi\n").unwrap();
+    assert!(!rpack(&["--ephemeral-key", plain.to_str().unwrap(), dir.join("invalid").to_str().unwrap()], &[]).status.success());
+    assert!(!rpack(&["--ephemeral-key", "--interpreter", "/bin/sh", "/bin/sh", dir.join("invalid").to_str().unwrap()], &[]).status.success());
+}
@@ -0,0 +1,93 @@
+//! Read the license of a program packed with `rpack --not-after ...`, and the files
+//! bundled with `rpack --resource`.
+//!
+//! ```no_run
+//! // Public key printed by `rpack keygen`
+//! const PUBLIC_KEY: [u8; 32] = [0; 32];
+//!
+//! match rpack_runtime::license(&PUBLIC_KEY) {
+//!     Some(license) if license.has_feature("export") => println!("export enabled"),
+//!     Some(_) => println!("export disabled"),
+//!     None => println!("not running from a licensed build"),
+//! }
+//!
+//! let config = rpack_runtime::resource("config.toml").map(std::io::read_to_string);
+//! ```
+
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+
+pub use container::license::{License, LicenseError};
+use container::license::{SignedLicense, LICENSE_ENV};
+use container::resources::{decode_env, RESOURCES_ENV};
+
+/// License of the running program, if the stub passed one and it is signed with
+/// `public_key`.
+///
+/// The stub has already checked the dates and hosts when the program started.
+pub fn license(public_key: &[u8; 32]) -> Option<License> {
+    from_env_value(&env::var(LICENSE_ENV).ok()?, public_key)
+}
+
+fn from_env_value(value: &str, public_key: &[u8; 32]) -> Option<License> {
+    let signed = SignedLicense::decode(value)?;
+    if signed.public_key != *public_key {
+        return None;
+    }
+    signed.verify()
+}
+
+/// Names of the files bundled with the running program.
+pub fn resource_names() -> Vec<String> {
+    let value = env::var(RESOURCES_ENV).unwrap_or_default();
+    decode_env(&value).map(|(name, _)| name.to_string()).collect()
+}
+
+/// Path of the bundled file `name`, for programs that pass it on to a library or a
+/// child process. The file lives in memory: the path is a `/proc/self/fd` entry.
+pub fn resource_path(name: &str) -> Option<PathBuf> {
+    let value = env::var(RESOURCES_ENV).ok()?;
+    let fd = fd_of(&value, name)?;
+    Some(PathBuf::from(format!("/proc/self/fd/{}", fd)))
+}
+
+/// The bundled file `name`, opened for reading from the start.
+pub fn resource(name: &str) -> Option<File> {
+    File::open(resource_path(name)?).ok()
+}
+
+fn fd_of(value: &str, name: &str) -> Option<i32> {
+    decode_env(value).find(|(entry, _)| *entry == name).map(|(_, fd)| fd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_env_value() {
+        let license = License {
+            licensee: None,
+            not_before: None,
+            not_after: Some(2000),
+            hosts: Vec::new(),
+            features: vec!["export".into()],
+            message: String::new(),
+        };
+        let signed = SignedLicense::sign(&license, &[3; 32]);
+        let public = signed.public_key;
+        assert_eq!(from_env_value(&signed.encode(), &public), Some(license.clone()));
+
+        // Signed by someone else
+        let other = SignedLicense::sign(&license, &[4; 32]).encode();
+        assert_eq!(from_env_value(&other, &public), None);
+        assert_eq!(from_env_value("not hex", &public), None);
+    }
+
+    #[test]
+    fn test_fd_of() {
+        assert_eq!(fd_of("config.toml=3:model.bin=4", "model.bin"), Some(4));
+        assert_eq!(fd_of("config.toml=3", "model"), None);
+    }
+}
@@ -1,31 +1,158 @@
-use libc::{c_char, c_long};
+#![cfg_attr(test, allow(unused_imports, unused_macros, dead_code))]
+
+use libc::{c_char, c_int, c_long, c_ulong, pid_t};
 use std::env;
-use std::ffi::CString;
-use std::fs::{self, File};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{FromRawFd, AsRawFd};
+use std::os::unix::net::UnixDatagram;
 use std::path::Path;
-use std::time::{Instant, Duration};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::arch::x86_64::{__cpuid, _rdtsc};
 use std::process;
-use lz4_flex::decompress;
-use aes::AES128;
-use whitebox::{decrypt_message, NTRUVector, WhiteData};
-use bincode;
+use lz4_flex::decompress_into;
+use aes::io::DecryptReader;
+use aes::secret::SecretBytes;
+use aes::{Decryptor, Mode, AES128};
+use whitebox::{DecryptContext, NTRUVector, WhiteData};
+use checksum::signature::{self, PUBLIC_KEY_SIZE};
 use checksum::validate_blake3;
+use container::audit::{AuditTarget, Category, Event, EventKind};
+use container::bundle;
+use container::license::LICENSE_ENV;
+use container::passphrase::{Passphrase, PassphraseSource, SHARE_SIZE};
+use container::resources::{self, Resource, RESOURCES_ENV};
+use container::script::{Interpreter, Script};
+use container::elf::{
+    find_pack, parse_program_headers, reserved_note, text_hashes, Pack, ProgramHeader, PF_X, PROGRAM_HEADER_SIZE, PT_LOAD, PT_PHDR,
+    RESERVED_NOTE_SIZE,
+};
+use container::trailer::{Section, Trailer, BLAKE3_SIZE, NONCE_SIZE};
+use fingerprint::environment::Environment;
+use fingerprint::{host_key_mask, Fingerprint};
+use container::{empty_public_key_slot, Config, Reaction, PUBLIC_KEY_MARKER, PUBLIC_KEY_SLOT_SIZE};
 use ctor::ctor;
-use raw_cpuid::CpuId;
+use raw_cpuid::{CpuId, Hypervisor};
 use rand::seq::SliceRandom;
+use rand::Rng;
+use zeroize::{Zeroize, Zeroizing};
+
+// The packer overwrites the key after the marker with its signing public key
+#[used]
+static PUBLIC_KEY_SLOT: [u8; PUBLIC_KEY_SLOT_SIZE] = empty_public_key_slot();
+
+// Empty `rpack` note in a section of its own. Its alignment differs from the other notes,
+// so the linker gives it a PT_NOTE of its own, which the packer turns into the PT_LOAD of
+// the pack data: GNU strip and objcopy cannot handle a program header table moved
+// elsewhere to make room for a new entry.
+#[repr(C, align(8))]
+struct ReservedNote([u8; RESERVED_NOTE_SIZE]);
+
+#[used]
+#[link_section = ".note.rpack"]
+static RESERVED_NOTE: ReservedNote = ReservedNote(reserved_note());
 
-const BIGMONKE_BYTES: &[u8] = include_bytes!("BIGMONKE");
+// Pack-time settings, read from the trailer the first time they are needed
+static CONFIG: OnceLock<Config> = OnceLock::new();
+// Pack data whose signature was checked along with the settings
+static PACK_DATA: OnceLock<&'static [u8]> = OnceLock::new();
+// int3 bytes in the critical functions, counted once their segment hashed as packed
+static BREAKPOINT_BASELINE: OnceLock<Vec<usize>> = OnceLock::new();
 
-const BLAKE3_SIZE: usize = 32;
+// Diagnostic builds (feature `diagnostics`, `rpack --diagnostics`) explain on stderr
+// why they stop. Production builds compile the messages out.
+#[cfg(feature = "diagnostics")]
+macro_rules! diag {
+    ($($arg:tt)*) => {
+        eprintln!("rpack-stub: {}", format_args!($($arg)*))
+    };
+}
+
+#[cfg(not(feature = "diagnostics"))]
+macro_rules! diag {
+    ($($arg:tt)*) => {};
+}
+
+// Report a failure code without reacting to it
+macro_rules! report {
+    ($code:expr, $($reason:tt)*) => {{
+        let _ = $code;
+        diag!("error E{}: {}", $code, format_args!($($reason)*));
+    }};
+}
+
+macro_rules! fail {
+    ($code:expr, $($reason:tt)*) => {{
+        report!($code, $($reason)*);
+        bait($code);
+    }};
+}
+
+// Failure codes printed by diagnostic builds
+#[cfg_attr(test, allow(dead_code))]
+mod code {
+    // Detections
+    pub const TRACED: u16 = 101;
+    pub const TIMING: u16 = 102;
+    pub const TIMING_DELAY: u16 = 103;
+    pub const VM_DETECTED: u16 = 104;
+    pub const TEXT_MODIFIED: u16 = 105;
+    pub const BREAKPOINT: u16 = 106;
+    pub const DEBUGGER_DETECTED: u16 = 107;
+    pub const CONTAINER_DETECTED: u16 = 108;
+    pub const EMULATOR_DETECTED: u16 = 109;
+    // Reading our own image
+    pub const SIGNATURE: u16 = 201;
+    pub const NOT_DUMPABLE: u16 = 202;
+    pub const TRAILER: u16 = 205;
+    pub const READ_A1: u16 = 206;
+    pub const READ_A2: u16 = 207;
+    pub const READ_WHITE_DATA: u16 = 208;
+    pub const DECODE_WHITE_DATA: u16 = 209;
+    pub const DECODE_A1: u16 = 210;
+    pub const DECODE_A2: u16 = 211;
+    // Unwrapping the payload
+    pub const WHITE_DATA_CHECK: u16 = 301;
+    pub const WHITEBOX_DECRYPT: u16 = 302;
+    pub const AES_KEY_CHECK: u16 = 303;
+    pub const PAYLOAD_NONCE: u16 = 305;
+    pub const PAYLOAD_DECRYPT: u16 = 306;
+    pub const COMPRESSED_CHECK: u16 = 307;
+    pub const DECOMPRESS: u16 = 308;
+    pub const ORIGINAL_CHECK: u16 = 309;
+    pub const WRONG_HOST: u16 = 310;
+    pub const NO_PASSPHRASE: u16 = 311;
+    pub const WRONG_PASSPHRASE: u16 = 312;
+    pub const RESOURCES: u16 = 313;
+    pub const PROGRAM_INDEX: u16 = 314;
+    // Running the payload
+    pub const MEMFD_CREATE: u16 = 401;
+    pub const MEMFD_WRITE: u16 = 402;
+    pub const MEMFD_SEAL: u16 = 403;
+    pub const ARGV: u16 = 404;
+    pub const ENVP: u16 = 405;
+    pub const EXEC: u16 = 406;
+    pub const DECOY: u16 = 407;
+    pub const GUARDIAN: u16 = 408;
+    pub const RESOURCE_MEMFD: u16 = 409;
+    pub const INTERPRETER: u16 = 410;
+    // License
+    pub const LICENSE_INVALID: u16 = 501;
+    pub const LICENSE_REFUSED: u16 = 502;
+}
 
 macro_rules! is_being_traced {
     () => {{
         let result = loop {
             let mut file = match File::open("/proc/self/status") {
                 Ok(f) => f,
-                Err(_) => break true,
+                // Without /proc there is nothing to check, which only the config may allow
+                Err(_) => break !(missing_proc_allowed() && !Path::new("/proc/self").exists()),
             };
             let mut contents = String::new();
             if file.read_to_string(&mut contents).is_err() {
@@ -52,36 +179,162 @@ macro_rules! is_being_traced {
 macro_rules! is_traced {
     () => {
         if is_being_traced!() {
-            // eprintln!("Tracing detected");
-            bait();
+            fail!(code::TRACED, "tracer detected (or /proc/self/status unreadable)");
         }
     };
 }
 
+// Time this thread spent runnable but waiting for a CPU. A busy machine delays us that
+// way without anyone stopping us, and a stopped thread is not runnable, so the timing
+// checks leave it out. Zero where schedstat is unavailable.
+fn run_delay() -> Duration {
+    fs::read_to_string("/proc/thread-self/schedstat")
+        .ok()
+        .and_then(|stat| stat.split_whitespace().nth(1)?.parse().ok())
+        .map_or(Duration::ZERO, Duration::from_nanos)
+}
+
+// Wall-clock time minus the time spent waiting for a CPU
+struct Stopwatch {
+    start: Instant,
+    run_delay: Duration,
+}
+
+impl Stopwatch {
+    fn start() -> Stopwatch {
+        Stopwatch { start: Instant::now(), run_delay: run_delay() }
+    }
+
+    fn elapsed(&self) -> Duration {
+        let waited = run_delay().saturating_sub(self.run_delay);
+        self.start.elapsed().saturating_sub(waited)
+    }
+}
+
 #[cfg(not(test))]
 macro_rules! timecheck {
     () => {
         {
             is_traced!(); // One more check won't hurt
-            let t1 = Instant::now();
-            let t2 = Instant::now();
-            if t2.duration_since(t1) > Duration::from_millis(1) {
-                // eprintln!("Timing check failed");
-                bait();
+            let stopwatch = Stopwatch::start();
+            let elapsed = stopwatch.elapsed();
+            if elapsed > Duration::from_millis(1) {
+                fail!(code::TIMING, "{:?} between two reads of the clock", elapsed);
             }
         }
     };
-    ($beg:expr, $delay:expr) => {
+    ($stopwatch:expr, $delay:expr) => {
         {
             is_traced!();
-            if Instant::now().duration_since($beg) > $delay {
-                // eprintln!("Timing check with delay failed");
-                bait();
+            let elapsed = $stopwatch.elapsed();
+            if elapsed > $delay {
+                fail!(code::TIMING_DELAY, "took {:?}, limit is {:?}", elapsed, $delay);
             }
         }
     };
 }
 
+// Code patched in memory, which the signature over the file cannot see
+#[cfg(not(test))]
+macro_rules! textcheck {
+    () => {
+        if own_text_hashes().as_ref() != Some(&config().text_hashes) {
+            fail!(code::TEXT_MODIFIED, "executable segment differs from the packed stub");
+        }
+        breakcheck!();
+    };
+}
+
+// Cheaper than textcheck: only looks for new breakpoints in the critical functions
+#[cfg(not(test))]
+macro_rules! breakcheck {
+    () => {
+        let counts = breakpoint_counts(&critical_functions());
+        if counts.as_ref() != Some(BREAKPOINT_BASELINE.get_or_init(|| counts.clone().unwrap_or_default())) {
+            fail!(code::BREAKPOINT, "breakpoint in a critical function");
+        }
+    };
+}
+
+/////////////////////////////////////////
+//      Debugger Detection Macros      //
+/////////////////////////////////////////
+
+// A process that is already traced cannot ask to be traced. Only a child asks, since
+// its parent becomes the tracer, and debuggers that follow forks trace it too.
+macro_rules! check_traceme {
+    () => {{
+        unsafe {
+            let child = libc::fork();
+            if child == 0 {
+                let refused = libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1;
+                libc::_exit(refused as c_int);
+            }
+            child > 0 && wait_exit_code(child) == Some(1)
+        }
+    }};
+}
+
+// Libraries the dynamic loader injected before our own code ran
+macro_rules! check_preload {
+    () => {{
+        ["LD_PRELOAD", "LD_AUDIT"].iter().any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+            || fs::metadata("/etc/ld.so.preload").is_ok_and(|metadata| metadata.len() > 0)
+    }};
+}
+
+macro_rules! check_parent_name {
+    () => {{
+        let parent = unsafe { libc::getppid() };
+        fs::read_to_string(format!("/proc/{}/comm", parent)).is_ok_and(|name| DEBUGGER_NAMES.contains(&name.trim_end()))
+    }};
+}
+
+// Hardware breakpoints live in the debug registers, which /proc does not show and only
+// a tracer can read: a child attaches to us, reads DR7 and detaches. Attaching fails
+// while a debugger is attached, which the tracer check covers.
+macro_rules! check_debug_registers {
+    () => {{
+        unsafe {
+            let parent = libc::getpid();
+            libc::prctl(PR_SET_PTRACER, PR_SET_PTRACER_ANY, 0, 0, 0);
+            let child = libc::fork();
+            if child == 0 {
+                let mut enabled = false;
+                if libc::ptrace(libc::PTRACE_ATTACH, parent, 0, 0) == 0 {
+                    let mut status = 0;
+                    libc::waitpid(parent, &mut status, libc::__WALL);
+                    let dr7 = libc::ptrace(libc::PTRACE_PEEKUSER, parent, DR7_OFFSET, 0);
+                    // L0-G3: one local and one global enable bit per breakpoint
+                    enabled = dr7 != -1 && dr7 & 0xff != 0;
+                    libc::ptrace(libc::PTRACE_DETACH, parent, 0, 0);
+                }
+                libc::_exit(enabled as c_int);
+            }
+            let enabled = child > 0 && wait_exit_code(child) == Some(1);
+            libc::prctl(PR_SET_PTRACER, 0, 0, 0, 0);
+            enabled
+        }
+    }};
+}
+
+// A debugger takes the SIGTRAP of an int3 for one of its own breakpoints and does not
+// pass it on by default; without one, our handler sees it
+macro_rules! check_sigtrap {
+    () => {{
+        unsafe {
+            SIGTRAP_HANDLED.store(false, Ordering::SeqCst);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigtrap as extern "C" fn(c_int) as usize;
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGTRAP, &action, &mut previous);
+            std::arch::asm!("int3");
+            libc::sigaction(libc::SIGTRAP, &previous, std::ptr::null_mut());
+        }
+        !SIGTRAP_HANDLED.load(Ordering::SeqCst)
+    }};
+}
+
 /////////////////////////////////////////
 //      VM Detection Macros            //
 // Could have done it in another file  //
@@ -190,435 +443,1233 @@ macro_rules! check_virtio_devices {
     }};
 }
 
+macro_rules! check_dmi {
+    () => {{
+        let vm_vendors = ["QEMU", "KVM", "VMware", "VirtualBox", "innotek", "Xen", "Bochs", "Parallels", "BHYVE", "Virtual Machine"];
+        ["product_name", "sys_vendor", "bios_vendor"].iter().any(|field| {
+            fs::read_to_string(Path::new("/sys/class/dmi/id").join(field))
+                .is_ok_and(|value| vm_vendors.iter().any(|vendor| value.contains(vendor)))
+        })
+    }};
+}
+
+// A hypervisor traps CPUID: the round trip takes thousands of cycles, against a few
+// hundred on bare metal. The fastest of several tries leaves out interrupts.
+macro_rules! check_cpuid_timing {
+    () => {{
+        let fastest = (0..16)
+            .map(|_| unsafe {
+                let start = _rdtsc();
+                __cpuid(0);
+                _rdtsc().wrapping_sub(start)
+            })
+            .min()
+            .unwrap_or(0);
+        fastest > CPUID_CYCLES_LIMIT
+    }};
+}
+
+/////////////////////////////////////////
+//      Container Detection Macros     //
+/////////////////////////////////////////
+macro_rules! check_container_files {
+    () => {{
+        // Docker, then Podman
+        ["/.dockerenv", "/run/.containerenv"].iter().any(|file| Path::new(file).exists())
+    }};
+}
+
+macro_rules! check_container_cgroup {
+    () => {{
+        let runtimes = ["docker", "kubepods", "libpod", "lxc", "containerd"];
+        fs::read_to_string("/proc/self/cgroup").is_ok_and(|cgroups| runtimes.iter().any(|runtime| cgroups.contains(runtime)))
+    }};
+}
+
+// Set by LXC, Podman and systemd-nspawn for the container's processes
+macro_rules! check_container_env {
+    () => {{
+        env::var_os("container").is_some()
+    }};
+}
+
+/////////////////////////////////////////
+//      Emulator Detection Macros      //
+/////////////////////////////////////////
+
+// QEMU's TCG, which also runs qemu-user and unicorn, reports its own CPU models.
+// KVM passes the host CPU through, or at least says it is KVM.
+macro_rules! check_emulated_cpu {
+    () => {{
+        let cpuid = CpuId::new();
+        let hypervisor = cpuid.get_hypervisor_info().map(|info| info.identify());
+        let qemu_model = cpuid.get_processor_brand_string().is_some_and(|brand| brand.as_str().contains("QEMU"));
+        hypervisor == Some(Hypervisor::QEMU) || (qemu_model && hypervisor.is_none())
+    }};
+}
+
+// qemu-user reads its settings from QEMU_* variables (QEMU_LD_PREFIX, QEMU_CPU...)
+macro_rules! check_qemu_env {
+    () => {{
+        env::vars_os().any(|(key, _)| key.as_bytes().starts_with(b"QEMU_"))
+    }};
+}
+
+// The kernel maps a vDSO into every x86-64 process; qemu-user does not always
+macro_rules! check_missing_vdso {
+    () => {{
+        unsafe { libc::getauxval(libc::AT_SYSINFO_EHDR) == 0 }
+    }};
+}
+
+// Emulators that count instructions rather than time can return the same TSC twice
+macro_rules! check_frozen_tsc {
+    () => {{
+        unsafe {
+            let start = _rdtsc();
+            __cpuid(0);
+            _rdtsc() <= start
+        }
+    }};
+}
+
+// Fastest CPUID round trip on bare metal, in TSC cycles, with a wide margin
+const CPUID_CYCLES_LIMIT: u64 = 1000;
+
+// Categories of the environment checks. The config says which ones the binary refuses
+// to run in, as it does for the debugger checks.
+const ENVIRONMENT_CATEGORIES: [Category; 3] = [Category::Vm, Category::Container, Category::Emulator];
+
+// Total weight of the checks of a category at which it counts as detected
+const SCORE_THRESHOLD: f32 = 1.5;
+
+fn refused(category: Category, config: &Config) -> bool {
+    match category {
+        Category::Vm => config.anti_vm,
+        Category::Container => config.anti_container,
+        Category::Emulator => config.anti_emulator,
+        Category::Debugger => config.anti_debugger,
+    }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+fn detection_code(category: Category) -> u16 {
+    match category {
+        Category::Vm => code::VM_DETECTED,
+        Category::Container => code::CONTAINER_DETECTED,
+        Category::Emulator => code::EMULATOR_DETECTED,
+        Category::Debugger => code::DEBUGGER_DETECTED,
+    }
+}
+
+// Check, weight and category
+type EnvironmentCheck = (fn() -> bool, f32, Category);
+
 #[cfg(not(test))]
 #[ctor]
-fn vm_detection() {
-    let start_time = Instant::now();
-    let checks: Vec<(fn() -> bool, f32)> = vec![
-        (|| check_hypervisor_flag!(), 2.0),
-        (|| check_vm_files!(), 1.0),
-        (|| check_mac_address!(), 1.0),
-        (|| check_disk_size!(), 1.0),
-        (|| check_uptime!(), 0.5),
-        (|| check_cpuid!(), 2.0),
-        (|| is_being_traced!(), 2.0),
-        (|| check_virtio_devices!(), 1.0),
+#[cfg_attr(not(feature = "diagnostics"), allow(unused_variables))]
+fn environment_detection() {
+    // Audits cover every category, in any environment
+    let auditing = config().audit.is_some();
+    if !auditing && !ENVIRONMENT_CATEGORIES.iter().any(|&category| refused(category, config())) {
+        return;
+    }
+    if !auditing && !config().trusted_environments.is_empty() {
+        let environment = Environment::collect();
+        if config().trusted_environments.iter().any(|trusted| trusted.matches(&environment)) {
+            diag!("trusted environment, skipping the VM, container and emulator checks");
+            return;
+        }
+    }
+    let stopwatch = Stopwatch::start();
+    let checks: Vec<EnvironmentCheck> = vec![
+        (|| check_hypervisor_flag!(), 2.0, Category::Vm),
+        (|| check_vm_files!(), 1.0, Category::Vm),
+        (|| check_mac_address!(), 1.0, Category::Vm),
+        (|| check_disk_size!(), 1.0, Category::Vm),
+        (|| check_uptime!(), 0.5, Category::Vm),
+        (|| check_cpuid!(), 2.0, Category::Vm),
+        (|| check_virtio_devices!(), 1.0, Category::Vm),
+        (|| check_dmi!(), 2.0, Category::Vm),
+        (|| check_cpuid_timing!(), 1.0, Category::Vm),
+        (|| check_container_files!(), 2.0, Category::Container),
+        (|| check_container_cgroup!(), 2.0, Category::Container),
+        (|| check_container_env!(), 1.0, Category::Container),
+        (|| check_emulated_cpu!(), 2.0, Category::Emulator),
+        (|| check_qemu_env!(), 1.0, Category::Emulator),
+        (|| check_missing_vdso!(), 1.0, Category::Emulator),
+        (|| check_frozen_tsc!(), 1.0, Category::Emulator),
     ];
 
-    timecheck!(start_time, Duration::from_millis(50));
+    timecheck!(stopwatch, Duration::from_millis(50));
 
-    let mut score = 0.0;
+    let mut scores = [0.0; ENVIRONMENT_CATEGORIES.len()];
     let mut rng = rand::thread_rng();
-    let mut shuffled_checks = checks;
+    let mut shuffled_checks: Vec<_> = checks
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, (_, _, category))| auditing || refused(category, config()))
+        .collect();
     shuffled_checks.shuffle(&mut rng);
 
-    for (check, weight) in shuffled_checks {
-        if check() {
-            score += weight;
+    for (index, (check, weight, category)) in shuffled_checks {
+        let fired = check();
+        audit(EventKind::Check { category, check: index, weight, fired });
+        if fired {
+            scores[category as usize] += weight;
+            diag!("{} check '{}' fired (weight {})", category, container::audit::ENVIRONMENT_CHECKS[index], weight);
         }
     }
 
-    if score >= 1.5 {
-        bait();
+    for category in ENVIRONMENT_CATEGORIES {
+        if auditing || refused(category, config()) {
+            score(category, scores[category as usize]);
+        }
     }
 }
 
+// Record a category's score and react if it reaches the threshold
 #[cfg(not(test))]
-fn bait() {
-    let name = match CString::new("meow") {
-        Ok(name) => name,
-        Err(_) => {
-            // eprintln!("Error creating CString for memfd_create");
-            process::exit(1);
+fn score(category: Category, score: f32) {
+    audit(EventKind::Score { category, score, threshold: SCORE_THRESHOLD });
+    if score >= SCORE_THRESHOLD {
+        fail!(detection_code(category), "{} score {} (threshold {})", category, score, SCORE_THRESHOLD);
+    }
+}
+
+// Parent processes that trace us
+const DEBUGGER_NAMES: [&str; 3] = ["gdb", "strace", "ltrace"];
+// Missing from libc for glibc targets
+const PR_SET_PTRACER: c_int = 0x59616d61;
+const PR_SET_PTRACER_ANY: c_ulong = c_ulong::MAX;
+// offsetof(struct user, u_debugreg[7]) on x86-64
+const DR7_OFFSET: usize = 848 + 7 * 8;
+
+static SIGTRAP_HANDLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigtrap(_signal: c_int) {
+    SIGTRAP_HANDLED.store(true, Ordering::SeqCst);
+}
+
+// Exit code of a child that exited, retrying when a signal interrupts the wait
+fn wait_exit_code(child: pid_t) -> Option<c_int> {
+    let mut status = 0;
+    loop {
+        match unsafe { libc::waitpid(child, &mut status, 0) } {
+            -1 if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) => continue,
+            -1 => return None,
+            _ => break,
         }
-    };
-    let fd = unsafe { libc::syscall(319, name.as_ptr(), 0) as i32 }; // 319 is SYS_memfd_create
-    if fd < 0 {
-        // eprintln!("Error in memfd_create");
-        process::exit(1);
     }
+    libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status))
+}
 
-    let mut memfd_file = unsafe { File::from_raw_fd(fd) };
-    if memfd_file.write_all(BIGMONKE_BYTES).is_err() {
-        // eprintln!("Error writing to memfd");
-        process::exit(1);
+// Not affected by trusted environments: debuggers are not part of anyone's deployment
+#[cfg(not(test))]
+#[ctor]
+#[cfg_attr(not(feature = "diagnostics"), allow(unused_variables))]
+fn debugger_detection() {
+    let auditing = config().audit.is_some();
+    if !auditing && !refused(Category::Debugger, config()) {
+        return;
     }
+    // The two weak checks stay below the threshold together: both fire on hosts with
+    // no debugger, so detection needs a tracer, a debugger parent, DR7 or SIGTRAP
+    let checks: Vec<(fn() -> bool, f32)> = vec![
+        (|| is_being_traced!(), 2.0),
+        // Also refused in sandboxes that forbid ptrace altogether
+        (|| check_traceme!(), 0.5),
+        // Also how some profilers and monitoring agents load
+        (|| check_preload!(), 0.5),
+        (|| check_parent_name!(), 1.5),
+        (|| check_debug_registers!(), 2.0),
+        (|| check_sigtrap!(), 2.0),
+    ];
 
-    let prog_path = format!("/proc/self/fd/{}", fd);
-    let prog_name = match CString::new(prog_path) {
-        Ok(name) => name,
-        Err(_) => {
-            // eprintln!("Error creating CString for execve");
-            process::exit(1);
-        }
-    };
-    let argv: [*const c_char; 2] = [prog_name.as_ptr(), std::ptr::null()];
-    const ENVP: [*const c_char; 1] = [std::ptr::null()];
+    let mut total = 0.0;
+    let mut shuffled_checks: Vec<_> = checks.into_iter().enumerate().collect();
+    shuffled_checks.shuffle(&mut rand::thread_rng());
 
-    unsafe {
-        libc::syscall(59, prog_name.as_ptr(), argv.as_ptr(), ENVP.as_ptr()); // 59 is SYS_execve
+    for (index, (check, weight)) in shuffled_checks {
+        let fired = check();
+        audit(EventKind::Check { category: Category::Debugger, check: index, weight, fired });
+        if fired {
+            total += weight;
+            diag!("debugger check '{}' fired (weight {})", container::audit::DEBUGGER_CHECKS[index], weight);
+        }
     }
 
-    // eprintln!("Failed to execute execve");
-    process::exit(1);
+    score(Category::Debugger, total);
 }
 
 #[cfg(not(test))]
-#[ctor]
-fn init_checksum_validation() {
-    let current_exe = match env::current_exe() {
-        Ok(path) => path,
-        Err(_) => {
-            // eprintln!("Failed to get current executable path");
-            bait();
+fn bait(code: u16) {
+    if config().audit.is_some() {
+        diag!("audit mode, not reacting");
+        audit(EventKind::Detection { code });
+        return;
+    }
+    diag!("reacting with {}", config().reaction);
+    match config().reaction {
+        Reaction::Exit(code) => process::exit(code),
+        Reaction::Decoy => run_decoy(),
+        Reaction::Corrupt(max_delay_ms) => {
+            // Look like the program crashed on its own, some time after the detection
+            let delay = rand::thread_rng().gen_range(0..=max_delay_ms);
+            std::thread::sleep(Duration::from_millis(delay as u64));
+            unsafe {
+                libc::signal(libc::SIGSEGV, libc::SIG_DFL);
+                libc::raise(libc::SIGSEGV);
+            }
+        }
+        Reaction::Log => {
+            eprintln!("warning: tampering detected");
             return;
         }
+    }
+    process::exit(1);
+}
+
+// Record an event if the binary was packed in audit mode
+#[cfg(not(test))]
+fn audit(kind: EventKind) {
+    if let Some(target) = &config().audit {
+        record(target, kind);
+    }
+}
+
+// Best effort: an audit must not stop the program
+#[cfg(not(test))]
+fn record(target: &AuditTarget, kind: EventKind) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let event = Event { time, pid: process::id(), kind };
+    let _ = match target {
+        // One write per line, so that concurrent runs do not interleave
+        AuditTarget::File(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(format!("{}\n", event).as_bytes())),
+        // RFC 3164 header: facility user, severity notice
+        AuditTarget::Syslog(socket) => UnixDatagram::unbound()
+            .and_then(|datagram| datagram.send_to(format!("<13>rpack[{}]: {}", event.pid, event).as_bytes(), socket))
+            .map(drop),
     };
-    let mut file = match File::open(&current_exe) {
-        Ok(f) => f,
-        Err(_) => {
-            // eprintln!("Failed to open current executable");
-            bait();
+}
+
+// Decrypt the decoy from our own image and replace ourselves with it
+fn run_decoy() {
+    let decoy = match read_decoy() {
+        Some(decoy) => decoy,
+        None => {
+            report!(code::DECOY, "failed to read the decoy");
             return;
         }
     };
-    let total_size = match file.metadata() {
-        Ok(metadata) => metadata.len(),
+    let name = match CString::new("meow") {
+        Ok(name) => name,
         Err(_) => {
-            // eprintln!("Failed to get file metadata");
-            bait();
+            report!(code::DECOY, "failed to create the memfd name");
             return;
         }
     };
-
-    if file.seek(SeekFrom::End(-(BLAKE3_SIZE as i64))).is_err() {
-        // eprintln!("Failed to seek to final hash");
-        bait();
-        return;
-    }
-    let mut final_hash = [0u8; BLAKE3_SIZE];
-    if file.read_exact(&mut final_hash).is_err() {
-        // eprintln!("Failed to read final hash");
-        bait();
+    let fd = unsafe { libc::syscall(319, name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) as i32 }; // 319 is SYS_memfd_create
+    if fd < 0 {
+        report!(code::DECOY, "memfd_create failed");
         return;
     }
 
-    let binary_size = total_size - (BLAKE3_SIZE as u64);
-    if file.seek(SeekFrom::Start(0)).is_err() {
-        // eprintln!("Failed to seek to start of binary");
-        bait();
-        return;
-    }
-    let mut binary_data = vec![0u8; binary_size as usize];
-    if file.read_exact(&mut binary_data).is_err() {
-        // eprintln!("Failed to read binary data");
-        bait();
+    let mut memfd_file = unsafe { File::from_raw_fd(fd) };
+    if memfd_file.write_all(&decoy).is_err() || !seal_memfd(fd) {
+        report!(code::DECOY, "failed to write the decoy to the memfd");
         return;
     }
 
-    if !validate_blake3(&binary_data, &final_hash) {
-        // eprintln!("ERROR: Binary integrity check failed");
-        bait();
-    }
+    let argv: [*const c_char; 2] = [name.as_ptr(), std::ptr::null()];
+    const ENVP: [*const c_char; 1] = [std::ptr::null()];
+    exec_fd(fd, &argv, &ENVP);
+    report!(code::DECOY, "failed to execute the decoy");
 }
 
-#[cfg(not(test))]
-fn main() {
-    timecheck!();
+// Decoy section: [key] [counter block] [AES-128-CTR ciphertext]
+fn read_decoy() -> Option<Vec<u8>> {
+    let data = pack_data();
+    let trailer = Trailer::parse(data, data.len() as u64).ok()?;
+    let section = trailer.decoy.slice(data)?;
+    let (key, encrypted) = section.split_first_chunk::<16>()?;
+    let (nonce, ciphertext) = encrypted.split_first_chunk::<16>()?;
+    let mut reader = DecryptReader::new(ciphertext, Decryptor::new(AES128::new(key), Mode::Ctr(*nonce)));
+    let mut decoy = vec![0u8; ciphertext.len()];
+    reader.read_exact(&mut decoy).ok()?;
+    Some(decoy)
+}
 
+// Make a memfd immutable before exec'ing it: no writes, no resizing.
+fn seal_memfd(fd: i32) -> bool {
+    let seals = libc::F_SEAL_WRITE | libc::F_SEAL_GROW | libc::F_SEAL_SHRINK;
+    // fcntl: SYS_fcntl = 72
+    unsafe { libc::syscall(72, fd as c_long, libc::F_ADD_SEALS as c_long, seals as c_long) == 0 }
+}
+
+// Exec the program in `fd`, with NULL-terminated argv and envp.
+// execveat(AT_EMPTY_PATH) does not need /proc; /proc/self/fd/N is the fallback
+// for kernels older than 3.19. Only returns on failure.
+fn exec_fd(fd: i32, argv: &[*const c_char], envp: &[*const c_char]) {
     unsafe {
-        // prctl: SYS_prctl = 157, PR_SET_DUMPABLE = 4
-        let ret = libc::syscall(157, 4 as c_long, 0, 0, 0, 0);
-        if ret == -1 {
-            // eprintln!("Failed to disable PR_SET_DUMPABLE");
-            bait();
-            return;
+        libc::syscall(322, fd as c_long, c"".as_ptr(), argv.as_ptr(), envp.as_ptr(), libc::AT_EMPTY_PATH as c_long); // 322 is SYS_execveat
+    }
+    if let Ok(prog_name) = CString::new(format!("/proc/self/fd/{}", fd)) {
+        unsafe {
+            libc::syscall(59, prog_name.as_ptr(), argv.as_ptr(), envp.as_ptr()); // 59 is SYS_execve
         }
     }
+}
 
-    let current_exe = match env::current_exe() {
-        Ok(path) => path,
-        Err(_) => {
-            // eprintln!("Failed to get current executable path");
-            bait();
-            return;
+// System interpreter of a script payload
+fn exec_path(path: &str, argv: &[*const c_char], envp: &[*const c_char]) {
+    if let Ok(path) = CString::new(path) {
+        unsafe {
+            libc::syscall(59, path.as_ptr(), argv.as_ptr(), envp.as_ptr()); // 59 is SYS_execve
         }
-    };
-    let mut file = match File::open(&current_exe) {
-        Ok(f) => f,
-        Err(_) => {
-            // eprintln!("Failed to open current executable");
-            bait();
-            return;
+    }
+}
+
+// Payload traced by the guardian, for its signal handler
+static GUARDED_CHILD: AtomicI32 = AtomicI32::new(0);
+
+// Signals the guardian passes on to the payload. Job control signals are caught too, so
+// that the guardian only stops once the payload has.
+const FORWARDED_SIGNALS: [c_int; 10] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGALRM,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+extern "C" fn forward_signal(signal: c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    // Signals from the kernel, such as the terminal's, reach the whole process group,
+    // the payload included. Only those sent to the guardian alone are passed on.
+    if unsafe { (*info).si_code } > 0 {
+        return;
+    }
+    let child = GUARDED_CHILD.load(Ordering::SeqCst);
+    if child > 0 {
+        unsafe { libc::kill(child, signal) };
+    }
+}
+
+// Fork a child that returns true once we trace it, to exec the payload. We stay behind
+// as its guardian: only one tracer can attach to a process. Returns false in the
+// guardian if the child could not be traced.
+fn fork_guardian(fd: i32) -> bool {
+    let mut pipe = [0; 2];
+    if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return false;
+    }
+    let guardian = unsafe { libc::getpid() };
+    let child = unsafe { libc::fork() };
+    if child == 0 {
+        let mut ready = 0u8;
+        unsafe {
+            libc::close(pipe[1]);
+            // Killed with the guardian, even before it traces us
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL as c_ulong, 0, 0, 0);
+            if libc::getppid() != guardian || libc::read(pipe[0], (&mut ready as *mut u8).cast(), 1) != 1 {
+                libc::_exit(1);
+            }
+            libc::close(pipe[0]);
         }
-    };
-    let fd = file.as_raw_fd();
+        return true;
+    }
     unsafe {
-        // fcntl: SYS_fcntl = 72, F_SETFD = 2, FD_CLOEXEC = 1
-        let ret = libc::syscall(72, fd as c_long, 2 as c_long, 1 as c_long);
-        if ret == -1 {
-            // eprintln!("Failed to set FD_CLOEXEC");
-            bait();
-            return;
+        libc::close(pipe[0]);
+        // The payload is the child's business, and the memfd holds it in clear
+        libc::close(fd);
+    }
+    let traced = child > 0
+        && unsafe { libc::ptrace(libc::PTRACE_SEIZE, child, 0 as c_long, libc::PTRACE_O_EXITKILL as c_long) } == 0
+        && unsafe { libc::write(pipe[1], [1u8].as_ptr().cast(), 1) } == 1;
+    // Without the go-ahead, the child exits
+    unsafe { libc::close(pipe[1]) };
+    if !traced {
+        if child > 0 {
+            wait_exit_code(child);
         }
+        return false;
     }
+    guard(child)
+}
 
-    let total_size = match file.metadata() {
-        Ok(metadata) => metadata.len(),
-        Err(_) => {
-            // eprintln!("Failed to get file metadata");
-            bait();
-            return;
+// Mirror the traced payload until it exits, then exit the same way
+fn guard(child: pid_t) -> ! {
+    GUARDED_CHILD.store(child, Ordering::SeqCst);
+    for signal in FORWARDED_SIGNALS {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = forward_signal as extern "C" fn(c_int, *mut libc::siginfo_t, *mut libc::c_void) as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigaction(signal, &action, std::ptr::null_mut());
         }
-    };
+    }
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(child, &mut status, libc::__WALL) } == -1 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+                continue;
+            }
+            process::exit(1);
+        }
+        if libc::WIFEXITED(status) {
+            process::exit(libc::WEXITSTATUS(status));
+        }
+        if libc::WIFSIGNALED(status) {
+            die_of(libc::WTERMSIG(status));
+        }
+        if !libc::WIFSTOPPED(status) {
+            continue;
+        }
+        let signal = libc::WSTOPSIG(status);
+        unsafe {
+            if status >> 16 == libc::PTRACE_EVENT_STOP {
+                if matches!(signal, libc::SIGSTOP | libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU) {
+                    // Group stop (Ctrl-Z, SIGSTOP): the payload stays stopped until it gets
+                    // SIGCONT, and we stop too so that the shell sees the job stop
+                    libc::ptrace(libc::PTRACE_LISTEN, child, 0 as c_long, 0 as c_long);
+                    libc::kill(libc::getpid(), libc::SIGSTOP);
+                    // Continued, maybe on our own
+                    libc::kill(child, libc::SIGCONT);
+                } else {
+                    // Woken up from a group stop
+                    libc::ptrace(libc::PTRACE_CONT, child, 0 as c_long, 0 as c_long);
+                }
+            } else {
+                // Signal delivery: let the payload have it
+                libc::ptrace(libc::PTRACE_CONT, child, 0 as c_long, signal as c_long);
+            }
+        }
+    }
+}
 
-    // Size of all hashes at the end:
-    // Blake3 (3): original, compressed, aes_key
-    const CHECKSUMS_SIZE: usize = 3 * BLAKE3_SIZE;
+// Terminate with the signal that terminated the payload
+fn die_of(signal: c_int) -> ! {
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+    process::exit(128 + signal);
+}
 
-    // Size of all size fields:
-    // 8 (encrypted_size) + 8 (a1_size) + 8 (a2_size) + 8 (white_data_size) + 8 (decompressed_size)
-    const SIZE_FIELDS_SIZE: usize = 40;
+// Volatile read: the compiler would otherwise fold the all-zero key into the code
+fn embedded_public_key() -> [u8; PUBLIC_KEY_SIZE] {
+    let slot = unsafe { std::ptr::read_volatile(&PUBLIC_KEY_SLOT) };
+    let mut key = [0u8; PUBLIC_KEY_SIZE];
+    key.copy_from_slice(&slot[PUBLIC_KEY_MARKER.len()..]);
+    key
+}
 
-    if file.seek(SeekFrom::End(-((BLAKE3_SIZE + CHECKSUMS_SIZE + SIZE_FIELDS_SIZE) as i64))).is_err() {
-        // eprintln!("Failed to seek to sizes");
-        bait();
-        return;
-    }
-    let mut sizes_bytes = [0u8; SIZE_FIELDS_SIZE];
-    if file.read_exact(&mut sizes_bytes).is_err() {
-        // eprintln!("Failed to read sizes");
-        bait();
-        return;
-    }
+// Payload key of a machine-bound binary: the slot packed for this host is the only one
+// that unmasks to a key with the right hash
+fn unwrap_host_key(whitebox_key: &[u8; 16], host_keys: &[[u8; 16]], key_hash: &[u8; 32]) -> Option<Zeroizing<[u8; 16]>> {
+    let mask = Zeroizing::new(host_key_mask(whitebox_key, &Fingerprint::collect().digest()));
+    host_keys
+        .iter()
+        .map(|slot| Zeroizing::new(std::array::from_fn(|i| slot[i] ^ mask[i])))
+        .find(|key: &Zeroizing<[u8; 16]>| validate_blake3(&**key, key_hash))
+}
 
-    let size_encrypted_payload = u64::from_le_bytes(sizes_bytes[0..8].try_into().unwrap());
-    let size_a1 = u64::from_le_bytes(sizes_bytes[8..16].try_into().unwrap());
-    let size_a2 = u64::from_le_bytes(sizes_bytes[16..24].try_into().unwrap());
-    let size_white_data = u64::from_le_bytes(sizes_bytes[24..32].try_into().unwrap());
-    let decompressed_size = u64::from_le_bytes(sizes_bytes[32..40].try_into().unwrap());
+// Longest passphrase read from a terminal or file descriptor
+const MAX_PASSPHRASE_LEN: usize = 1024;
 
-    // Read all checksums
-    if file.seek(SeekFrom::End(-((BLAKE3_SIZE + CHECKSUMS_SIZE) as i64))).is_err() {
-        // eprintln!("Failed to seek to checksums");
-        bait();
-        return;
+// Read one line, without its terminator, into a buffer that never reallocates
+fn read_line(file: &mut File) -> Option<Zeroizing<Vec<u8>>> {
+    let mut line = Zeroizing::new(Vec::with_capacity(MAX_PASSPHRASE_LEN));
+    let mut byte = [0u8; 1];
+    while file.read(&mut byte).ok()? == 1 && byte[0] != b'\n' {
+        if line.len() == MAX_PASSPHRASE_LEN {
+            return None;
+        }
+        line.push(byte[0]);
     }
-    let mut checksum_bytes = vec![0u8; CHECKSUMS_SIZE];
-    if file.read_exact(&mut checksum_bytes).is_err() {
-        // eprintln!("Failed to read checksums");
-        bait();
-        return;
+    if line.last() == Some(&b'\r') {
+        line.pop();
     }
+    byte.zeroize();
+    Some(line)
+}
 
-    // Extract checksums
-    // Format: [original_hash] [compressed_hash] [aes_key_hash]
-    let mut offset = 0;
-    let mut original_hash = [0u8; BLAKE3_SIZE];
-    original_hash.copy_from_slice(&checksum_bytes[offset..offset + BLAKE3_SIZE]);
-    offset += BLAKE3_SIZE;
-
-    let mut compressed_hash = [0u8; BLAKE3_SIZE];
-    compressed_hash.copy_from_slice(&checksum_bytes[offset..offset + BLAKE3_SIZE]);
-    offset += BLAKE3_SIZE;
-
-    let mut aes_key_hash = [0u8; BLAKE3_SIZE];
-    aes_key_hash.copy_from_slice(&checksum_bytes[offset..offset + BLAKE3_SIZE]);
-
-    // Calculate offsets for data sections
-    // Format: [STUB] [encrypted_data] [a1] [a2] [white_data] [sizes] [checksums] [final_hash]
-    let metadata_size = (SIZE_FIELDS_SIZE + CHECKSUMS_SIZE + BLAKE3_SIZE) as u64;
-    let start_white_data = total_size - metadata_size - size_white_data;
-    let start_a2 = start_white_data - size_a2;
-    let start_a1 = start_a2 - size_a1;
-    let start_encrypted_payload = start_a1 - size_encrypted_payload;
-
-    if start_encrypted_payload >= total_size || start_a1 >= total_size ||
-       start_a2 >= total_size || start_white_data >= total_size {
-        // eprintln!("Invalid offsets");
-        bait();
-        return;
+// Prompt on the controlling terminal with echo turned off
+fn prompt_tty() -> Option<Zeroizing<Vec<u8>>> {
+    let mut tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    let fd = tty.as_raw_fd();
+    let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        return None;
     }
+    let mut silent = saved;
+    silent.c_lflag &= !(libc::ECHO | libc::ECHONL);
+    tty.write_all(b"Passphrase: ").ok()?;
+    unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &silent) };
+    let line = read_line(&mut tty);
+    unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &saved) };
+    let _ = tty.write_all(b"\n");
+    line
+}
 
-    if file.seek(SeekFrom::Start(start_encrypted_payload)).is_err() {
-        // eprintln!("Failed to seek to encrypted payload");
-        bait();
-        return;
+fn read_passphrase(source: &PassphraseSource) -> Option<Zeroizing<Vec<u8>>> {
+    match source {
+        PassphraseSource::Tty => prompt_tty(),
+        PassphraseSource::Env(name) => env::var_os(name).map(|value| Zeroizing::new(value.into_vec())),
+        PassphraseSource::Fd(fd) => {
+            // fcntl: F_GETFD = 1. Only take descriptors we were given, and close them
+            // so the payload does not inherit them
+            if unsafe { libc::fcntl(*fd, libc::F_GETFD) } == -1 {
+                return None;
+            }
+            read_line(&mut unsafe { File::from_raw_fd(*fd) })
+        }
     }
-    let mut encrypted_payload = vec![0u8; size_encrypted_payload as usize];
-    if file.read_exact(&mut encrypted_payload).is_err() {
-        // eprintln!("Failed to read encrypted payload");
-        bait();
-        return;
+}
+
+// The passphrase share of the key, asking up to three times on a terminal. A wrong or
+// missing passphrase is an operator error, not tampering: say so and exit.
+fn passphrase_share(settings: &Passphrase) -> Zeroizing<[u8; SHARE_SIZE]> {
+    let attempts = if settings.source == PassphraseSource::Tty { 3 } else { 1 };
+    for _ in 0..attempts {
+        let Some(passphrase) = read_passphrase(&settings.source) else {
+            report!(code::NO_PASSPHRASE, "no passphrase from {}", settings.source);
+            eprintln!("No passphrase given ({})", settings.source);
+            process::exit(1);
+        };
+        if let Some(share) = settings.share(&passphrase) {
+            return share;
+        }
+        eprintln!("Wrong passphrase");
     }
+    report!(code::WRONG_PASSPHRASE, "passphrase check failed");
+    process::exit(1);
+}
 
-    if file.seek(SeekFrom::Start(start_a1)).is_err() {
-        // eprintln!("Failed to seek to a1");
-        bait();
-        return;
+// The program headers the kernel mapped, and the load bias to add to their addresses:
+// no need for /proc or for the file, wherever tools moved things around in it
+fn own_program_headers() -> Option<(Vec<ProgramHeader>, usize)> {
+    let (phdr, phnum) = unsafe { (libc::getauxval(libc::AT_PHDR) as usize, libc::getauxval(libc::AT_PHNUM) as usize) };
+    if phdr == 0 {
+        return None;
     }
-    let mut serialized_a1 = vec![0u8; size_a1 as usize];
-    if file.read_exact(&mut serialized_a1).is_err() {
-        // eprintln!("Failed to read serialized a1");
-        bait();
-        return;
+    let table = unsafe { std::slice::from_raw_parts(phdr as *const u8, phnum * PROGRAM_HEADER_SIZE) };
+    let headers = parse_program_headers(table);
+    let bias = phdr.checked_sub(headers.iter().find(|header| header.kind == PT_PHDR)?.vaddr as usize)?;
+    Some((headers, bias))
+}
+
+// Reads one of our PT_LOAD segments, all of which the kernel mapped
+fn mapped_segment(bias: usize) -> impl Fn(&ProgramHeader) -> Option<&'static [u8]> {
+    move |header| {
+        let start = bias.checked_add(usize::try_from(header.vaddr).ok()?)?;
+        Some(unsafe { std::slice::from_raw_parts(start as *const u8, usize::try_from(header.filesz).ok()?) })
     }
+}
 
-    if file.seek(SeekFrom::Start(start_a2)).is_err() {
-        // eprintln!("Failed to seek to a2");
-        bait();
-        return;
+fn find_own_pack() -> Option<Pack<'static>> {
+    let (headers, bias) = own_program_headers()?;
+    find_pack(&headers, mapped_segment(bias))
+}
+
+// Hashes of our executable segments as they are now, to compare with `Config::text_hashes`
+fn own_text_hashes() -> Option<Vec<[u8; 32]>> {
+    let (headers, bias) = own_program_headers()?;
+    text_hashes(&headers, mapped_segment(bias))
+}
+
+// How far into each critical function we look for breakpoints
+const BREAKPOINT_SCAN_LEN: usize = 64;
+const INT3: u8 = 0xcc;
+
+// Functions a debugger would break on to catch the key or the payload
+#[cfg(not(test))]
+fn critical_functions() -> [usize; 6] {
+    [
+        unpack_and_exec as fn() as usize,
+        load_config as fn() -> Option<Config> as usize,
+        bait as fn(u16) as usize,
+        unwrap_host_key as fn(&[u8; 16], &[[u8; 16]], &[u8; 32]) -> Option<Zeroizing<[u8; 16]>> as usize,
+        passphrase_share as fn(&Passphrase) -> Zeroizing<[u8; SHARE_SIZE]> as usize,
+        exec_fd as fn(i32, &[*const c_char], &[*const c_char]) as usize,
+    ]
+}
+
+// int3 bytes in the first bytes of each function. Code can contain 0xcc legitimately,
+// so the counts only mean something compared with a baseline. None if a function is
+// outside our executable segments.
+fn breakpoint_counts(functions: &[usize]) -> Option<Vec<usize>> {
+    let (headers, bias) = own_program_headers()?;
+    let text = headers
+        .iter()
+        .filter(|header| header.kind == PT_LOAD && header.flags & PF_X != 0)
+        .map(mapped_segment(bias))
+        .collect::<Option<Vec<_>>>()?;
+    functions
+        .iter()
+        .map(|&function| {
+            let segment = text.iter().find(|segment| segment.as_ptr_range().contains(&(function as *const u8)))?;
+            let start = function - segment.as_ptr() as usize;
+            let end = segment.len().min(start + BREAKPOINT_SCAN_LEN);
+            Some(segment[start..end].iter().filter(|&&byte| byte == INT3).count())
+        })
+        .collect()
+}
+
+// Check the signature of our own image, then read the pack-time settings from it.
+// Until the signature is checked nothing in the image can be trusted, not even the
+// reaction to tampering, so failures here just exit.
+fn load_config() -> Option<Config> {
+    let pack = find_own_pack()?;
+    let trailer = Trailer::parse(pack.data, pack.data.len() as u64).ok()?;
+    if !signature::verify(&embedded_public_key(), &pack.digest, &trailer.signature) {
+        return None;
     }
-    let mut serialized_a2 = vec![0u8; size_a2 as usize];
-    if file.read_exact(&mut serialized_a2).is_err() {
-        // eprintln!("Failed to read serialized a2");
-        bait();
+    let config = container::decode(trailer.config.slice(pack.data)?)?;
+    PACK_DATA.get_or_init(|| pack.data);
+    Some(config)
+}
+
+// The signed pack data, once the config is loaded
+fn pack_data() -> &'static [u8] {
+    PACK_DATA.get().copied().unwrap_or_default()
+}
+
+#[cfg(not(test))]
+fn missing_proc_allowed() -> bool {
+    config().allow_missing_proc
+}
+
+#[cfg(test)]
+fn missing_proc_allowed() -> bool {
+    false
+}
+
+#[cfg(not(test))]
+fn config() -> &'static Config {
+    CONFIG.get_or_init(|| match load_config() {
+        Some(config) => {
+            if let Some(target) = &config.audit {
+                record(target, EventKind::Start);
+            }
+            config
+        }
+        None => {
+            report!(code::SIGNATURE, "signature check failed or config unreadable");
+            process::exit(1);
+        }
+    })
+}
+
+// NULL-terminated C array built from argv or environ entries
+fn to_c_array<'a>(items: impl Iterator<Item = &'a OsStr>) -> Option<(Vec<CString>, Vec<*const c_char>)> {
+    let strings = items
+        .map(|item| CString::new(item.as_bytes()).ok())
+        .collect::<Option<Vec<CString>>>()?;
+    let mut pointers: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+    pointers.push(std::ptr::null());
+    Some((strings, pointers))
+}
+
+#[cfg(not(test))]
+#[ctor]
+fn init_signature_validation() {
+    config();
+}
+
+// Also takes the breakpoint baseline, before anything else could patch the code
+#[cfg(not(test))]
+#[ctor]
+fn init_text_validation() {
+    textcheck!();
+}
+
+// Refuse to run outside the license terms, with the licensor's message rather than a reaction
+#[cfg(not(test))]
+#[ctor]
+#[cfg_attr(not(feature = "diagnostics"), allow(unused_variables))]
+fn init_license_validation() {
+    let Some(signed) = &config().license else {
         return;
+    };
+    let license = match signed.verify() {
+        Some(license) if signed.public_key == embedded_public_key() => license,
+        _ => {
+            fail!(code::LICENSE_INVALID, "license not signed with the packing key");
+            process::exit(1);
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    if let Err(e) = license.check(now, || Fingerprint::collect().digest()) {
+        report!(code::LICENSE_REFUSED, "{}", e);
+        eprintln!("{}", license.message);
+        process::exit(1);
     }
+}
 
-    if file.seek(SeekFrom::Start(start_white_data)).is_err() {
-        // eprintln!("Failed to seek to white data");
-        bait();
-        return;
+#[cfg(not(test))]
+fn main() {
+    unpack_and_exec();
+    // Only reached when unpacking failed and the reaction let us continue
+    process::exit(1);
+}
+
+// What unpack_and_exec decrypts: the main payload or a program of a multi-call bundle
+#[cfg_attr(test, allow(dead_code))]
+struct Payload<'a> {
+    encrypted: Option<&'a [u8]>,
+    key: Zeroizing<[u8; 16]>,
+    decompressed_size: u64,
+    original_hash: [u8; BLAKE3_SIZE],
+    compressed_hash: [u8; BLAKE3_SIZE],
+    // Only the main payload can be a script
+    script: Option<&'a Script>,
+}
+
+// Bundled files: [counter block] [AES-128-CTR ciphertext], or nothing
+fn decrypt_resources(section: &[u8], key: &[u8; 16]) -> Option<Vec<Resource>> {
+    if section.is_empty() {
+        return Some(Vec::new());
     }
-    let mut serialized_white_data = vec![0u8; size_white_data as usize];
-    if file.read_exact(&mut serialized_white_data).is_err() {
-        // eprintln!("Failed to read serialized WhiteData");
-        bait();
-        return;
+    let (nonce, ciphertext) = section.split_first_chunk::<{ NONCE_SIZE as usize }>()?;
+    let mut reader = DecryptReader::new(ciphertext, Decryptor::new(AES128::new(key), Mode::Ctr(*nonce)));
+    let mut compressed = SecretBytes::zeroed(ciphertext.len());
+    reader.read_exact(&mut compressed).ok()?;
+    resources::decompress(&compressed)
+}
+
+// One sealed memfd per bundled file. Without MFD_CLOEXEC, so that the payload inherits them.
+fn resource_memfds(bundled: &[Resource]) -> Option<Vec<File>> {
+    bundled
+        .iter()
+        .map(|resource| {
+            let name = CString::new(resource.name.as_str()).ok()?;
+            let fd = unsafe { libc::syscall(319, name.as_ptr(), libc::MFD_ALLOW_SEALING) as i32 }; // 319 is SYS_memfd_create
+            if fd < 0 {
+                return None;
+            }
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            file.write_all(&resource.data).ok()?;
+            // Programs that read the descriptor itself start at the beginning
+            file.rewind().ok()?;
+            seal_memfd(fd).then_some(file)
+        })
+        .collect()
+}
+
+#[cfg(not(test))]
+fn unpack_and_exec() {
+    timecheck!();
+    textcheck!();
+
+    unsafe {
+        // prctl: SYS_prctl = 157, PR_SET_DUMPABLE = 4
+        let ret = libc::syscall(157, 4 as c_long, 0, 0, 0, 0);
+        if ret == -1 {
+            fail!(code::NOT_DUMPABLE, "failed to disable PR_SET_DUMPABLE");
+            return;
+        }
     }
 
-    // Deserialize
-    let white_data: WhiteData = match bincode::deserialize(&serialized_white_data) {
-        Ok(data) => data,
-        Err(_) => {
-            // eprintln!("Failed to deserialize WhiteData");
-            bait();
+    let passphrase_share = config().passphrase.as_ref().map(passphrase_share);
+
+    let data = pack_data();
+    let trailer = match Trailer::parse(data, data.len() as u64).ok() {
+        Some(trailer) => trailer,
+        None => {
+            fail!(code::TRAILER, "invalid trailer");
             return;
         }
     };
-    let a1: NTRUVector = match bincode::deserialize(&serialized_a1) {
-        Ok(data) => data,
-        Err(_) => {
-            // eprintln!("Failed to deserialize a1");
-            bait();
+
+    let serialized_a1 = match trailer.a1.slice(data) {
+        Some(bytes) => bytes,
+        None => {
+            fail!(code::READ_A1, "failed to read a1");
             return;
         }
     };
-    let a2: NTRUVector = match bincode::deserialize(&serialized_a2) {
-        Ok(data) => data,
-        Err(_) => {
-            // eprintln!("Failed to deserialize a2");
-            bait();
+    let serialized_a2 = match trailer.a2.slice(data) {
+        Some(bytes) => bytes,
+        None => {
+            fail!(code::READ_A2, "failed to read a2");
+            return;
+        }
+    };
+    let serialized_white_data = match trailer.white_data.slice(data) {
+        Some(bytes) => bytes,
+        None => {
+            fail!(code::READ_WHITE_DATA, "failed to read WhiteData");
             return;
         }
     };
 
-    // NTRUVector checksums
-    if !a1.verify_checksum() || !a2.verify_checksum() {
-        // eprintln!("ERROR: NTRUVector checksum verification failed");
-        bait();
-        return;
-    }
+    // Deserialize
+    let white_data: WhiteData = match container::decode(serialized_white_data) {
+        Some(data) => data,
+        None => {
+            fail!(code::DECODE_WHITE_DATA, "failed to deserialize WhiteData");
+            return;
+        }
+    };
+    let a1: NTRUVector = match container::decode(serialized_a1) {
+        Some(data) => data,
+        None => {
+            fail!(code::DECODE_A1, "failed to deserialize a1");
+            return;
+        }
+    };
+    let a2: NTRUVector = match container::decode(serialized_a2) {
+        Some(data) => data,
+        None => {
+            fail!(code::DECODE_A2, "failed to deserialize a2");
+            return;
+        }
+    };
 
     // Decrypt the AES key
-    let decrypted_bits = decrypt_message(&white_data, &a1, &a2, a1.degree, a1.modulus);
-    let mut aes_key = [0u8; 16];
+    breakcheck!();
+    let context = match DecryptContext::new(&white_data, a1.degree, a1.modulus) {
+        Some(context) => context,
+        None => {
+            fail!(code::WHITE_DATA_CHECK, "WhiteData checksum or consistency check failed");
+            return;
+        }
+    };
+    let mut decrypted_bits = match context.decrypt(&a1, &a2) {
+        Some(bits) => bits,
+        None => {
+            fail!(code::WHITEBOX_DECRYPT, "whitebox decryption failed");
+            return;
+        }
+    };
+    let mut aes_key = Zeroizing::new([0u8; 16]);
     for i in 0..16 {
         for j in 0..8 {
             let bit = decrypted_bits[i * 8 + j] as u8;
             aes_key[i] |= bit << j;
         }
     }
+    if let Some(bits) = decrypted_bits.as_slice_mut() {
+        bits.zeroize();
+    }
+    if let Some(share) = passphrase_share {
+        for (byte, share) in aes_key.iter_mut().zip(share.iter()) {
+            *byte ^= share;
+        }
+    }
 
-    if !validate_blake3(&aes_key, &aes_key_hash) {
-        // eprintln!("ERROR: AES key verification failed");
-        bait();
+    if !config().host_keys.is_empty() {
+        aes_key = match unwrap_host_key(&aes_key, &config().host_keys, &trailer.aes_key_hash) {
+            Some(key) => key,
+            None => {
+                fail!(code::WRONG_HOST, "not packed for this host (compare with `rpack fingerprint`)");
+                return;
+            }
+        };
+    }
+    if !validate_blake3(&*aes_key, &trailer.aes_key_hash) {
+        fail!(code::AES_KEY_CHECK, "AES key hash mismatch");
         return;
     }
 
-    let aes = AES128::new(&aes_key);
-    let padded_compressed_data = (aes.decrypt)(&aes, &encrypted_payload);
-    let compressed_data = match aes::unpad_pkcs7(&padded_compressed_data) {
-        Some(data) => data,
-        None => {
-            // eprintln!("Invalid padding in AES decryption");
-            bait();
+    // A multi-call bundle runs the program it is invoked as, if any
+    let mut args: Vec<_> = env::args_os().collect();
+    let main_payload = Payload {
+        encrypted: trailer.payload.slice(data),
+        key: aes_key.clone(),
+        decompressed_size: trailer.decompressed_size,
+        original_hash: trailer.original_hash,
+        compressed_hash: trailer.compressed_hash,
+        script: config().script.as_ref(),
+    };
+    let payload = if trailer.programs.len == 0 {
+        main_payload
+    } else {
+        let Some((programs, encrypted)) = trailer.programs.slice(data).and_then(bundle::parse) else {
+            fail!(code::PROGRAM_INDEX, "invalid program index");
             return;
+        };
+        match bundle::select(&programs, args.first().map(|arg| arg.as_os_str()), args.get(1).map(|arg| arg.as_os_str())) {
+            Some((index, named_by_argument)) => {
+                let program = &programs[index];
+                diag!("running bundled program '{}'", program.name);
+                // Like busybox: `bundle ls -l` runs `ls -l`
+                if named_by_argument {
+                    args.remove(0);
+                }
+                Payload {
+                    encrypted: Section { offset: program.offset, len: program.len }.slice(encrypted),
+                    key: Zeroizing::new(program.key(&aes_key)),
+                    decompressed_size: program.decompressed_size,
+                    original_hash: program.original_hash,
+                    compressed_hash: program.compressed_hash,
+                    script: None,
+                }
+            }
+            None => main_payload,
         }
     };
 
-    if !validate_blake3(&compressed_data, &compressed_hash) {
-        // eprintln!("ERROR: Compressed data verification failed");
-        bait();
+    // Encrypted payload: [counter block] [AES-128-CTR ciphertext]
+    breakcheck!();
+    let Some((nonce, ciphertext)) = payload.encrypted.and_then(<[u8]>::split_first_chunk::<{ NONCE_SIZE as usize }>) else {
+        fail!(code::PAYLOAD_NONCE, "failed to read the payload nonce");
+        return;
+    };
+    let decryptor = Decryptor::new(AES128::new(&payload.key), Mode::Ctr(*nonce));
+    let mut reader = DecryptReader::new(ciphertext, decryptor);
+    let mut compressed_data = SecretBytes::zeroed(ciphertext.len());
+    if reader.read_exact(&mut compressed_data).is_err() {
+        fail!(code::PAYLOAD_DECRYPT, "failed to decrypt the payload");
         return;
     }
-
-    let decompressed_data = match decompress(&compressed_data, decompressed_size as usize) {
-        Ok(data) => data,
-        Err(_) => {
-            // eprintln!("Failed to decompress");
-            bait();
+    // The reader owns the key schedule, which is wiped when it is dropped
+    drop(reader);
+    drop(payload.key);
+    let bundled = match trailer.resources.slice(data).and_then(|section| decrypt_resources(section, &aes_key)) {
+        Some(bundled) => bundled,
+        None => {
+            fail!(code::RESOURCES, "failed to decrypt or decompress the bundled files");
             return;
         }
     };
+    drop(aes_key);
+
+    if !validate_blake3(&compressed_data, &payload.compressed_hash) {
+        fail!(code::COMPRESSED_CHECK, "compressed payload hash mismatch");
+        return;
+    }
 
-    if !validate_blake3(&decompressed_data, &original_hash) {
-        // eprintln!("ERROR: Original binary verification failed");
-        bait();
+    let mut decompressed_data = SecretBytes::zeroed(payload.decompressed_size as usize);
+    if decompress_into(&compressed_data, &mut decompressed_data).ok() != Some(decompressed_data.len()) {
+        fail!(code::DECOMPRESS, "decompression failed or size mismatch");
         return;
     }
+    drop(compressed_data);
+
+    if !validate_blake3(&decompressed_data, &payload.original_hash) {
+        fail!(code::ORIGINAL_CHECK, "original binary hash mismatch");
+        return;
+    }
+
+    // Before the timed part: bundled files can be large
+    let Some(resource_files) = resource_memfds(&bundled) else {
+        fail!(code::RESOURCE_MEMFD, "failed to pass the bundled files in memfds");
+        return;
+    };
+    let resources_env = resources::encode_env(bundled.iter().map(|resource| resource.name.as_str()).zip(resource_files.iter().map(File::as_raw_fd)));
+    // A bundled interpreter is one of the bundled files
+    let interpreter_fd = match payload.script.map(|script| &script.interpreter) {
+        Some(Interpreter::Bundled(name)) => match bundled.iter().position(|resource| &resource.name == name) {
+            Some(index) => resource_files[index].as_raw_fd(),
+            None => {
+                fail!(code::INTERPRETER, "bundled interpreter '{}' not found", name);
+                return;
+            }
+        },
+        _ => -1,
+    };
+    drop(bundled);
 
-    let timecheck_start = Instant::now();
+    let stopwatch = Stopwatch::start();
     let name = match CString::new("meow") {
         Ok(name) => name,
         Err(_) => {
-            // eprintln!("Error creating CString for memfd_create");
-            bait();
+            fail!(code::MEMFD_CREATE, "failed to create the memfd name");
             return;
         }
     };
-    let fd = unsafe { libc::syscall(319, name.as_ptr(), 0) as i32 }; // 319 is SYS_memfd_create
+    // The interpreter of a script opens it through /proc/self/fd, so it must survive exec
+    let flags = if payload.script.is_some() { libc::MFD_ALLOW_SEALING } else { libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING };
+    let fd = unsafe { libc::syscall(319, name.as_ptr(), flags) as i32 }; // 319 is SYS_memfd_create
     if fd < 0 {
-        // eprintln!("Error in memfd_create");
-        bait();
+        fail!(code::MEMFD_CREATE, "memfd_create failed");
         return;
     }
 
     let mut memfd_file = unsafe { File::from_raw_fd(fd) };
     if memfd_file.write_all(&decompressed_data).is_err() {
-        // eprintln!("Failed to write to memfd");
-        bait();
+        fail!(code::MEMFD_WRITE, "failed to write the payload to the memfd");
+        return;
+    }
+    drop(decompressed_data);
+    if !seal_memfd(fd) {
+        fail!(code::MEMFD_SEAL, "failed to seal the memfd");
         return;
     }
-    timecheck!(timecheck_start, Duration::from_millis(50));
+    timecheck!(stopwatch, Duration::from_millis(50));
+    textcheck!();
 
-    let prog_path = format!("/proc/self/fd/{}", fd);
-    let prog_name = match CString::new(prog_path) {
-        Ok(name) => name,
-        Err(_) => {
-            // eprintln!("Error creating CString for execve");
-            bait();
+    // Forward our own argv (argv[0] included, so programs that re-exec themselves
+    // go through the stub again) and environment.
+    // The passphrase stays with us
+    let passphrase_env = match config().passphrase.as_ref().map(|settings| &settings.source) {
+        Some(PassphraseSource::Env(name)) => Some(std::ffi::OsString::from(name)),
+        _ => None,
+    };
+    // The license and resources variables only ever come from us
+    let license = config().license.as_ref().map(|license| license.encode());
+    let resources_env = (!resource_files.is_empty()).then_some(resources_env);
+    let vars: Vec<_> = env::vars_os()
+        .filter(|(key, _)| key != LICENSE_ENV && key != RESOURCES_ENV && Some(key) != passphrase_env.as_ref())
+        .chain(license.map(|license| (LICENSE_ENV.into(), license.into())))
+        .chain(resources_env.map(|value| (RESOURCES_ENV.into(), value.into())))
+        .map(|(key, value)| {
+            let mut var = key;
+            var.push("=");
+            var.push(value);
+            var
+        })
+        .collect();
+    // A script gets the user's arguments after its own path
+    let script_args: Option<Vec<OsString>> = payload.script.map(|script| script.argv(fd, args.iter().skip(1).cloned()));
+    let argv = if let Some(script_args) = &script_args {
+        to_c_array(script_args.iter().map(|arg| arg.as_os_str()))
+    } else if args.is_empty() {
+        to_c_array(std::iter::once(OsStr::from_bytes(name.as_bytes())))
+    } else {
+        to_c_array(args.iter().map(|arg| arg.as_os_str()))
+    };
+    let (_argv_strings, argv) = match argv {
+        Some(argv) => argv,
+        None => {
+            fail!(code::ARGV, "argument contains a NUL byte");
+            return;
+        }
+    };
+    let (_envp_strings, envp) = match to_c_array(vars.iter().map(|var| var.as_os_str())) {
+        Some(envp) => envp,
+        None => {
+            fail!(code::ENVP, "environment variable contains a NUL byte");
             return;
         }
     };
-    let argv: [*const c_char; 2] = [prog_name.as_ptr(), std::ptr::null()];
-    const ENVP: [*const c_char; 1] = [std::ptr::null()];
 
-    unsafe {
-        libc::syscall(59, prog_name.as_ptr(), argv.as_ptr(), ENVP.as_ptr()); // 59 is SYS_execve
+    if config().guardian && !fork_guardian(fd) {
+        fail!(code::GUARDIAN, "failed to trace the payload");
+        return;
+    }
+    match payload.script.map(|script| &script.interpreter) {
+        None => exec_fd(fd, &argv, &envp),
+        Some(interpreter) => {
+            match interpreter {
+                Interpreter::System(path) => exec_path(path, &argv, &envp),
+                Interpreter::Bundled(_) => exec_fd(interpreter_fd, &argv, &envp),
+            }
+            fail!(code::INTERPRETER, "failed to execute the interpreter {:?}", interpreter);
+            return;
+        }
     }
 
-    // eprintln!("Failed to execute execve");
-    bait();
+    fail!(code::EXEC, "failed to execute the payload");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aes::io::EncryptWriter;
+    use aes::Encryptor;
+    use std::io::SeekFrom;
 
     #[test]
     fn test_not_traced() {
-        assert_eq!(is_being_traced!(), false);
+        assert!(!is_being_traced!());
+    }
+
+    #[test]
+    fn test_stopwatch_counts_sleep() {
+        // Sleeping is not waiting for a CPU, so it counts
+        let stopwatch = Stopwatch::start();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(stopwatch.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_no_debugger() {
+        assert!(!check_traceme!());
+        assert!(!check_parent_name!());
+        assert!(!check_debug_registers!());
+        assert!(!check_sigtrap!());
+    }
+
+    #[test]
+    fn test_not_emulated() {
+        assert!(!check_emulated_cpu!());
+        assert!(!check_missing_vdso!());
+        assert!(!check_frozen_tsc!());
+    }
+
+    #[test]
+    fn test_text_hashes_match_the_file() {
+        let image = fs::read("/proc/self/exe").unwrap();
+        let headers = container::elf::file_program_headers(&image).unwrap();
+        let in_file = text_hashes(&headers, container::elf::file_segment(&image)).unwrap();
+        assert!(!in_file.is_empty());
+        assert_eq!(own_text_hashes(), Some(in_file));
+    }
+
+    #[test]
+    fn test_breakpoint_counts() {
+        let function = test_breakpoint_counts as fn() as usize;
+        let counts = breakpoint_counts(&[function, seal_memfd as fn(i32) -> bool as usize]).unwrap();
+        assert_eq!(breakpoint_counts(&[function]).unwrap(), counts[..1]);
+        // Data is not code
+        assert_eq!(breakpoint_counts(&[&PUBLIC_KEY_SLOT as *const _ as usize]), None);
+    }
+
+    #[test]
+    fn test_sealed_memfd_is_immutable() {
+        let name = CString::new("test").unwrap();
+        let fd = unsafe { libc::syscall(319, name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) as i32 };
+        assert!(fd >= 0);
+        let mut memfd_file = unsafe { File::from_raw_fd(fd) };
+        memfd_file.write_all(b"payload").unwrap();
+        assert!(seal_memfd(fd));
+
+        let err = memfd_file.write_all(b"patched").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+        assert_eq!(memfd_file.set_len(0).unwrap_err().raw_os_error(), Some(libc::EPERM));
+        let mut contents = String::new();
+        memfd_file.seek(SeekFrom::Start(0)).unwrap();
+        memfd_file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "payload");
+    }
+
+    #[test]
+    fn test_resource_memfds() {
+        let bundled = vec![Resource { name: "config.toml".into(), data: b"threads = 4\n".to_vec() }];
+        let key = [9; 16];
+        let nonce = [1; NONCE_SIZE as usize];
+        let mut writer = EncryptWriter::new(nonce.to_vec(), Encryptor::new(AES128::new(&key), Mode::Ctr(nonce)));
+        writer.write_all(&resources::compress(&bundled)).unwrap();
+        let section = writer.finish().unwrap();
+        assert_eq!(decrypt_resources(&section, &key), Some(bundled.clone()));
+        assert_eq!(decrypt_resources(&section, &[8; 16]), None);
+        assert_eq!(decrypt_resources(&[], &key), Some(Vec::new()));
+
+        let mut files = resource_memfds(&bundled).unwrap();
+        let fd = files[0].as_raw_fd();
+        // Inherited across exec, readable from the start, and sealed
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC, 0);
+        let mut contents = String::new();
+        files[0].read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "threads = 4\n");
+        assert_eq!(fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap().to_string_lossy(), "/memfd:config.toml (deleted)");
+        assert_eq!(files[0].write_all(b"x").unwrap_err().raw_os_error(), Some(libc::EPERM));
     }
 }
@@ -0,0 +1,7 @@
+#!/bin/sh -e
+# Packed as a script: reads a name, greets it with the arguments and exits with 3
+
+read -r name
+echo "hello $name, $# argument(s): $*"
+echo "to stderr" >&2
+exit 3
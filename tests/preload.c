@@ -0,0 +1,3 @@
+// Library that changes nothing, preloaded like a profiler or an allocator would be
+
+int rpack_preloaded = 1;
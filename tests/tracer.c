@@ -0,0 +1,19 @@
+// Minimal debugger: runs its arguments under ptrace and, like gdb, does not pass
+// SIGTRAP on to the program
+
+#include <signal.h>
+#include <stdio.h>
+#include <sys/ptrace.h>
+#include <sys/wait.h>
+#include <unistd.h>
+int main(int argc, char **argv) {
+    pid_t child = fork();
+    if (child == 0) { ptrace(PTRACE_TRACEME, 0, 0, 0); execv(argv[1], argv + 1); return 127; }
+    int status;
+    while (waitpid(child, &status, 0) > 0) {
+        if (WIFEXITED(status)) return WEXITSTATUS(status);
+        int sig = WIFSTOPPED(status) ? WSTOPSIG(status) : 0;
+        ptrace(PTRACE_CONT, child, 0, sig == SIGTRAP ? 0 : sig);
+    }
+    return 1;
+}
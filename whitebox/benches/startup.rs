@@ -0,0 +1,67 @@
+// Startup latency of the stub's key unwrap: deserialize the WhiteData, build the
+// DecryptContext and decrypt one key. Fails if the median exceeds the budget.
+//
+//     cargo bench -p whitebox --bench startup
+//
+// The budget (in milliseconds) can be overridden with WB_STARTUP_BUDGET_MS.
+
+use std::time::{Duration, Instant};
+
+use numpy::ndarray::Array1;
+use whitebox::{create_whitebox, encrypt_func, DecryptContext, NTRUVector, WhiteData};
+
+const ITERATIONS: usize = 20;
+const DEFAULT_BUDGET_MS: u64 = 20;
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn main() {
+    let budget = Duration::from_millis(
+        std::env::var("WB_STARTUP_BUDGET_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .unwrap_or(DEFAULT_BUDGET_MS),
+    );
+
+    let (pub_data, white_data) = create_whitebox();
+    let key_bits: Array1<i64> = Array1::from_iter((0..pub_data.degree).map(|i| (i < 128 && i % 3 == 0) as i64));
+    let (a1, a2) = encrypt_func(&key_bits, &pub_data.pka, &pub_data.pkb, pub_data.degree, pub_data.modulus);
+    let serialized_white_data = bincode::serialize(&white_data).unwrap();
+    let serialized_a1 = bincode::serialize(&a1).unwrap();
+    let serialized_a2 = bincode::serialize(&a2).unwrap();
+
+    let mut deserialize = Vec::new();
+    let mut prepare = Vec::new();
+    let mut decrypt = Vec::new();
+    let mut total = Vec::new();
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let white_data: WhiteData = bincode::deserialize(&serialized_white_data).unwrap();
+        let a1: NTRUVector = bincode::deserialize(&serialized_a1).unwrap();
+        let a2: NTRUVector = bincode::deserialize(&serialized_a2).unwrap();
+        let deserialized = Instant::now();
+        let context = DecryptContext::new(&white_data, a1.degree, a1.modulus).unwrap();
+        let prepared = Instant::now();
+        let bits = context.decrypt(&a1, &a2).unwrap();
+        let done = Instant::now();
+        assert_eq!(bits, key_bits);
+
+        deserialize.push(deserialized - start);
+        prepare.push(prepared - deserialized);
+        decrypt.push(done - prepared);
+        total.push(done - start);
+    }
+
+    let total = median(total);
+    println!("deserialize  {:>10.3?}", median(deserialize));
+    println!("prepare      {:>10.3?}", median(prepare));
+    println!("decrypt      {:>10.3?}", median(decrypt));
+    println!("total        {:>10.3?} (budget {:?})", total, budget);
+    if total > budget {
+        eprintln!("startup latency is over budget");
+        std::process::exit(1);
+    }
+}
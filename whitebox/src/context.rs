@@ -0,0 +1,289 @@
+use crate::lattice::{Array1i64, NTRUVector, WhiteData};
+use checksum::crt::{mod_inverse, CrtBasis};
+use numpy::ndarray::Array1;
+use zeroize::Zeroize;
+
+// Each table entry packs one 5-bit value per CRT modulus.
+const FIELD_BITS: usize = 5;
+const TABLE_SIDE: usize = 1 << FIELD_BITS;
+const TABLE_SIZE: usize = TABLE_SIDE * TABLE_SIDE;
+const MAX_K: usize = u32::BITS as usize / FIELD_BITS;
+// Products of two residues must fit in an i64
+const MAX_MODULUS: i64 = 1 << 31;
+
+/// `WhiteData` checked and preprocessed for decryption.
+///
+/// Building the context verifies the checksums and precomputes the CRT constants
+/// and NTT tables once, so `decrypt` only does table lookups and modular arithmetic.
+pub struct DecryptContext<'a> {
+    degree: usize,
+    modulus: i64,
+    k: usize,
+    chal: u8,
+    rotate: usize,
+    mask: &'a [i64],
+    // fb[dim * TABLE_SIZE + a * TABLE_SIDE + b], same for sb
+    fb: &'a [u32],
+    sb: &'a [u32],
+    beta: CrtBasis,
+    beta_p: CrtBasis,
+    // (product of beta)^-1 and the modulus, reduced over beta_p
+    minv_m_p: Vec<i64>,
+    n_m_p: Vec<i64>,
+    // root^i and unroot^i
+    root_pows: Vec<i64>,
+    unroot_pows: Vec<i64>,
+    ninv: i64,
+    bit_reverse: Vec<usize>,
+}
+
+impl<'a> DecryptContext<'a> {
+    /// Returns None if the whitebox data is inconsistent or its checksums do not match.
+    pub fn new(white_data: &'a WhiteData, degree: usize, modulus: i64) -> Option<DecryptContext<'a>> {
+        let params = [
+            white_data.root,
+            white_data.unroot,
+            white_data.ninv,
+            white_data.k as i64,
+            white_data.rotate as i64,
+            white_data.chal as i64,
+        ];
+        if checksum::compute_crt_checksum(&white_data.beta) != white_data.beta_checksum
+            || checksum::compute_crt_checksum(&white_data.beta_p) != white_data.beta_p_checksum
+            || checksum::compute_crt_checksum(&white_data.mask) != white_data.mask_checksum
+            || checksum::compute_crt_checksum(&params) != white_data.data_checksum
+        {
+            return None;
+        }
+
+        let k = white_data.k;
+        if !degree.is_power_of_two()
+            || !(2..=MAX_MODULUS).contains(&modulus)
+            || k == 0
+            || k > MAX_K
+            || white_data.beta.len() != k
+            || white_data.beta_p.len() != k
+            || white_data.mask.len() != degree
+            || white_data.rotate >= degree
+            || white_data.fb.len() != degree * TABLE_SIZE
+            || white_data.sb.len() != degree * TABLE_SIZE
+        {
+            return None;
+        }
+        // Residues index the 32x32 tables
+        let fits_table = |b: &i64| *b as usize <= TABLE_SIDE;
+        if !white_data.beta.iter().all(fits_table) || !white_data.beta_p.iter().all(fits_table) {
+            return None;
+        }
+
+        let beta = CrtBasis::new(&white_data.beta).ok()?;
+        let beta_p = CrtBasis::new(&white_data.beta_p).ok()?;
+        let minv = mod_inverse(beta.product(), beta_p.product())?;
+
+        let powers = |base: i64| {
+            let mut pows = Vec::with_capacity(degree);
+            let mut temp = 1;
+            for _ in 0..degree {
+                pows.push(temp);
+                temp = (temp * base).rem_euclid(modulus);
+            }
+            pows
+        };
+        let levels = degree.trailing_zeros() as usize;
+
+        Some(DecryptContext {
+            degree,
+            modulus,
+            k,
+            chal: white_data.chal,
+            rotate: white_data.rotate,
+            mask: &white_data.mask,
+            fb: &white_data.fb,
+            sb: &white_data.sb,
+            minv_m_p: beta_p.to_residues(minv),
+            n_m_p: beta_p.to_residues(modulus),
+            beta,
+            beta_p,
+            root_pows: powers(white_data.root.rem_euclid(modulus)),
+            unroot_pows: powers(white_data.unroot.rem_euclid(modulus)),
+            ninv: white_data.ninv.rem_euclid(modulus),
+            bit_reverse: (0..degree).map(|i| bit_reverse(i, levels)).collect(),
+        })
+    }
+
+    /// Decrypt the message bits of (a1, a2). Returns None if the vectors do not match the context.
+    ///
+    /// Intermediate values are wiped; the caller should zeroize the returned bits.
+    pub fn decrypt(&self, a1: &NTRUVector, a2: &NTRUVector) -> Option<Array1i64> {
+        for a in [a1, a2] {
+            if a.degree != self.degree || a.modulus != self.modulus || a.vector.len() != self.degree || a.ntt {
+                return None;
+            }
+            if !a.verify_checksum() {
+                return None;
+            }
+        }
+
+        let mut x: Vec<i64> = a1.vector.to_vec();
+        let mut y: Vec<i64> = a2.vector.to_vec();
+        self.forward_ntt(&mut x);
+        self.forward_ntt(&mut y);
+
+        let mut z: Vec<i64> = (0..self.degree).map(|dim| self.mont_mult(dim, x[dim], y[dim])).collect();
+        self.inverse_ntt(&mut z);
+
+        let half = self.modulus / 2;
+        let mut m = Array1::zeros(self.degree);
+        for i in 0..self.degree {
+            let (m_val, mask) = if self.chal == 2 {
+                (z[(i + self.rotate) % self.degree], self.mask[i])
+            } else {
+                (z[i], 0)
+            };
+            let bit = (m_val + mask).rem_euclid(2);
+            m[i] = if m_val > half { 1 - bit } else { bit };
+        }
+        x.zeroize();
+        y.zeroize();
+        z.zeroize();
+        Some(m)
+    }
+
+    fn mont_mult(&self, dim: usize, a: i64, b: i64) -> i64 {
+        let k = self.k;
+        let fb = &self.fb[dim * TABLE_SIZE..(dim + 1) * TABLE_SIZE];
+        let sb = &self.sb[dim * TABLE_SIZE..(dim + 1) * TABLE_SIZE];
+        let beta = self.beta.moduli();
+        let beta_p = self.beta_p.moduli();
+
+        // First box lookup
+        let mut q = [0i64; MAX_K];
+        for i in 0..k {
+            let index = (a.rem_euclid(beta[i]) as usize) * TABLE_SIDE + b.rem_euclid(beta[i]) as usize;
+            q[i] = field(fb[index], i);
+        }
+        let q_crt = self.beta.reconstruct(&q[..k]);
+
+        // Second box lookup
+        let mut r = [0i64; MAX_K];
+        for i in 0..k {
+            let index = (a.rem_euclid(beta_p[i]) as usize) * TABLE_SIDE + b.rem_euclid(beta_p[i]) as usize;
+            let base = (q_crt.rem_euclid(beta_p[i]) * self.n_m_p[i] % beta_p[i]) * self.minv_m_p[i];
+            r[i] = (base + field(sb[index], i)).rem_euclid(beta_p[i]);
+        }
+        let r_crt = self.beta_p.reconstruct(&r[..k]);
+
+        (r_crt as i128 * self.beta.product() as i128).rem_euclid(self.modulus as i128) as i64
+    }
+
+    fn forward_ntt(&self, v: &mut [i64]) {
+        // The vectors come from the file: reduce them before multiplying
+        for (x, pow) in v.iter_mut().zip(&self.root_pows) {
+            *x = (x.rem_euclid(self.modulus) * pow).rem_euclid(self.modulus);
+        }
+        self.butterflies(v, &self.root_pows);
+    }
+
+    fn inverse_ntt(&self, v: &mut [i64]) {
+        self.butterflies(v, &self.unroot_pows);
+        for (x, pow) in v.iter_mut().zip(&self.unroot_pows) {
+            *x = (*x * self.ninv % self.modulus * pow).rem_euclid(self.modulus);
+        }
+    }
+
+    // Iterative Cooley-Tukey with twiddles pows[2 * j]
+    fn butterflies(&self, v: &mut [i64], pows: &[i64]) {
+        let n = self.degree;
+        for i in 0..n {
+            let j = self.bit_reverse[i];
+            if j > i {
+                v.swap(i, j);
+            }
+        }
+        let mut size = 2;
+        while size <= n {
+            let halfsize = size / 2;
+            let tablestep = n / size;
+            for i in (0..n).step_by(size) {
+                for (k, j) in (i..i + halfsize).enumerate() {
+                    let l = j + halfsize;
+                    let left = v[j];
+                    let right = (v[l] * pows[2 * k * tablestep]).rem_euclid(self.modulus);
+                    v[j] = (left + right).rem_euclid(self.modulus);
+                    v[l] = (left - right).rem_euclid(self.modulus);
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+fn field(entry: u32, i: usize) -> i64 {
+    ((entry >> (FIELD_BITS * i)) as usize % TABLE_SIDE) as i64
+}
+
+fn bit_reverse(x: usize, bits: usize) -> usize {
+    let mut y = 0;
+    let mut x = x;
+    for _ in 0..bits {
+        y = (y << 1) | (x & 1);
+        x >>= 1;
+    }
+    y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_wb::generate_whitebox_data;
+    use crate::encrypt_func;
+
+    fn small_whitebox() -> (crate::PubEncData, WhiteData) {
+        generate_whitebox_data(16, 1231873, &[13, 16, 19, 27, 29], &[11, 17, 23, 25, 31], 5, 2)
+    }
+
+    #[test]
+    fn test_decrypt_roundtrip() {
+        let (pub_data, white_data) = small_whitebox();
+        let context = DecryptContext::new(&white_data, pub_data.degree, pub_data.modulus).unwrap();
+        let message: Array1i64 = Array1::from_iter((0..16).map(|i| (i * 7 % 3 % 2) as i64));
+        let (a1, a2) = encrypt_func(&message, &pub_data.pka, &pub_data.pkb, pub_data.degree, pub_data.modulus);
+        assert_eq!(context.decrypt(&a1, &a2).unwrap(), message);
+        assert_eq!(crate::decrypt_message(&white_data, &a1, &a2, pub_data.degree, pub_data.modulus), message);
+    }
+
+    #[test]
+    fn test_rejects_tampered_data() {
+        let (pub_data, white_data) = small_whitebox();
+
+        let mut tampered = white_data.clone();
+        tampered.mask[0] ^= 1;
+        assert!(DecryptContext::new(&tampered, pub_data.degree, pub_data.modulus).is_none());
+
+        let mut tampered = white_data.clone();
+        tampered.rotate += 1;
+        assert!(DecryptContext::new(&tampered, pub_data.degree, pub_data.modulus).is_none());
+
+        let mut truncated = white_data.clone();
+        truncated.sb.pop();
+        assert!(DecryptContext::new(&truncated, pub_data.degree, pub_data.modulus).is_none());
+
+        let context = DecryptContext::new(&white_data, pub_data.degree, pub_data.modulus).unwrap();
+        let (mut a1, a2) = encrypt_func(&Array1::zeros(16), &pub_data.pka, &pub_data.pkb, pub_data.degree, pub_data.modulus);
+        a1.vector[0] += 1;
+        assert!(context.decrypt(&a1, &a2).is_none());
+    }
+
+    #[test]
+    fn test_forged_vectors_do_not_overflow() {
+        let (pub_data, white_data) = small_whitebox();
+        let context = DecryptContext::new(&white_data, pub_data.degree, pub_data.modulus).unwrap();
+        let (mut a1, mut a2) = encrypt_func(&Array1::zeros(16), &pub_data.pka, &pub_data.pkb, pub_data.degree, pub_data.modulus);
+        a1.vector.fill(i64::MAX);
+        a1.update_checksum();
+        a2.vector.fill(i64::MIN);
+        a2.update_checksum();
+        assert!(context.decrypt(&a1, &a2).is_some());
+        assert!(DecryptContext::new(&white_data, pub_data.degree, i64::MAX).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::lattice::{NTRUVector, PubEncData, WhiteData};
+use checksum::crt::{goto_crt, mod_inverse, CrtBasis};
 use rand::Rng;
 use rand_distr::Normal;
 use std::collections::HashMap;
@@ -123,33 +124,6 @@ fn bit_reverse(x: usize, bits: usize) -> usize {
     y
 }
 
-fn goto_crt(x: i64, base: &[i64]) -> Vec<i64> {
-    let crt_values = base.iter().map(|&b| x.rem_euclid(b)).collect();    
-    crt_values
-}
-
-fn xgcd(b: i64, n: i64) -> (i64, i64, i64) {
-    let mut x0 = 1;
-    let mut x1 = 0;
-    let mut y0 = 0;
-    let mut y1 = 1;
-    let mut b = b;
-    let mut n = n;
-    while n != 0 {
-        let q = b / n;
-        let temp = n;
-        n = b % n;
-        b = temp;
-        let temp = x1;
-        x1 = x0 - q * x1;
-        x0 = temp;
-        let temp = y1;
-        y1 = y0 - q * y1;
-        y0 = temp;
-    }
-    (b, x0, y0)
-}
-
 fn key_gen(degree: usize, q: i64) -> (NTRUVector, NTRUVector, NTRUVector) {
     let mut rng = rand::thread_rng();
     let normal = Normal::new(0.0, 1.0).unwrap();
@@ -203,7 +177,7 @@ fn encrypt(m: &[i64], pka: &NTRUVector, pkb: &NTRUVector, degree: usize, modulus
 }
 
 fn print_progress(m: usize, n: usize, step: usize) {
-    if m % step == 0 {
+    if m.is_multiple_of(step) {
         println!("{:.1}%", m as f64 * 100.0 / n as f64);
     }
 }
@@ -219,7 +193,7 @@ fn prepare_first_box_mm3(sk: &NTRUVector, a1_r: &NTRUVector, a2_r: &NTRUVector,
     tmp_sz.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let n = tmp_sk.modulus;
-    let (_, n_inv, _) = xgcd(n, m);
+    let n_inv = mod_inverse(n, m).expect("modulus must be coprime with beta");
     let n_inv_m = goto_crt(n_inv, beta);
     let mut fb = HashMap::new();
     for dim in 0..tmp_sk.degree {
@@ -260,7 +234,7 @@ fn prepare_second_box_mm3(sk: &NTRUVector, a1_r: &NTRUVector, a2_r: &NTRUVector,
     tmp_sz.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let m_p: i64 = beta_p.iter().product();
-    let (_, m_inv, _) = xgcd(m, m_p);
+    let m_inv = mod_inverse(m, m_p).expect("beta and beta_p must be coprime");
     let m_inv_m_p = goto_crt(m_inv, beta_p);
     let mut sb = HashMap::new();
     for dim in 0..tmp_sk.degree {
@@ -300,7 +274,7 @@ fn prepare_first_box_mm2(sk: &NTRUVector, a1_o: &NTRUVector, a2_o: &NTRUVector,
     tmp_sz.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let n = tmp_sk.modulus;
-    let (_, n_inv, _) = xgcd(n, m);
+    let n_inv = mod_inverse(n, m).expect("modulus must be coprime with beta");
     let n_inv_m = goto_crt(n_inv, beta);
     let mut fb = HashMap::new();
     for dim in 0..tmp_sk.degree {
@@ -341,7 +315,7 @@ fn prepare_second_box_mm2(sk: &NTRUVector, a1_o: &NTRUVector, a2_o: &NTRUVector,
     tmp_sz.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let m_p: i64 = beta_p.iter().product();
-    let (_, m_inv, _) = xgcd(m, m_p);
+    let m_inv = mod_inverse(m, m_p).expect("beta and beta_p must be coprime");
     let m_inv_m_p = goto_crt(m_inv, beta_p);
     let mut sb = HashMap::new();
     for dim in 0..tmp_sk.degree {
@@ -374,7 +348,7 @@ fn prepare_first_box_mm(sk: &mut NTRUVector, root: i64, _unroot: i64, _ninv: i64
     sk.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let n = sk.modulus;
-    let (_, n_inv, _) = xgcd(n, m);
+    let n_inv = mod_inverse(n, m).expect("modulus must be coprime with beta");
     let n_inv_m = goto_crt(n_inv, beta);
     let mut fb = HashMap::new();
     for dim in 0..sk.degree {
@@ -406,7 +380,7 @@ fn prepare_second_box_mm(sk: &mut NTRUVector, root: i64, _unroot: i64, _ninv: i6
     sk.goto_ntt(root);
     let m: i64 = beta.iter().product();
     let m_p: i64 = beta_p.iter().product();
-    let (_, m_inv, _) = xgcd(m, m_p);
+    let m_inv = mod_inverse(m, m_p).expect("beta and beta_p must be coprime");
     let m_inv_m_p = goto_crt(m_inv, beta_p);
     let mut sb = HashMap::new();
     for dim in 0..sk.degree {
@@ -479,12 +453,26 @@ fn find_generator(totient: i64, modulus: i64) -> i64 {
     panic!("No generator exists");
 }
 
+// Flatten the per-dimension 32x32 tables into one array indexed by [dim][a][b].
+fn flatten_tables(tables: &HashMap<String, Vec<Vec<i64>>>, prefix: &str, degree: usize) -> Vec<u32> {
+    let mut flat = Vec::with_capacity(degree * 32 * 32);
+    for dim in 0..degree {
+        let table = &tables[&format!("{}_dim_{}", prefix, dim)];
+        flat.extend(table.iter().flatten().map(|&v| u32::try_from(v).expect("Table entry out of range")));
+    }
+    flat
+}
+
 fn find_primitive_root(degree: usize, totient: i64, modulus: i64) -> i64 {
     let gen = find_generator(totient, modulus);
     pow(gen, totient / degree as i64, modulus)
 }
 
 pub fn generate_whitebox_data(degree: usize, modulus: i64, beta: &[i64], beta_p: &[i64], k: usize, chal: u8) -> (PubEncData, WhiteData) {
+    // The tables reconstruct over beta and beta_p, and invert their products into each other.
+    if let Err(e) = CrtBasis::new(&[beta, beta_p].concat()) {
+        panic!("Invalid CRT bases: {}", e);
+    }
     let (pka, pkb, sk) = key_gen(degree, modulus);
     let mut rng = rand::thread_rng();
 
@@ -506,8 +494,8 @@ pub fn generate_whitebox_data(degree: usize, modulus: i64, beta: &[i64], beta_p:
     let (a1_ma, a2_ma) = encrypt(&mask, &pka, &pkb, degree, modulus);
 
     let root = find_primitive_root(2 * degree, modulus - 1, modulus);
-    let unroot = xgcd(root, modulus).1;
-    let ninv = xgcd(degree as i64, modulus).1;
+    let unroot = mod_inverse(root, modulus).expect("root must be invertible");
+    let ninv = mod_inverse(degree as i64, modulus).expect("degree must be invertible");
 
     let (fb, sb) = match chal {
         0 => {
@@ -533,6 +521,8 @@ pub fn generate_whitebox_data(degree: usize, modulus: i64, beta: &[i64], beta_p:
         }
         _ => panic!("Invalid challenge level"),
     };
+    let fb = flatten_tables(&fb, "fb", degree);
+    let sb = flatten_tables(&sb, "sb", degree);
 
     let beta_checksum = checksum::compute_crt_checksum(beta);
     let beta_p_checksum = checksum::compute_crt_checksum(beta_p);
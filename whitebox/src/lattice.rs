@@ -1,5 +1,4 @@
 use numpy::ndarray::Array1;
-use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
 pub type Array1i64 = Array1<i64>;
@@ -91,8 +90,9 @@ pub struct WhiteData {
     pub mask: Vec<i64>,
     pub rotate: usize,
     pub chal: u8,
-    pub fb: HashMap<String, Vec<Vec<i64>>>,
-    pub sb: HashMap<String, Vec<Vec<i64>>>,
+    // First and second box tables, flattened: fb[dim * 1024 + a * 32 + b]
+    pub fb: Vec<u32>,
+    pub sb: Vec<u32>,
     pub beta_checksum: [u8; 32],
     pub beta_p_checksum: [u8; 32],
     pub mask_checksum: [u8; 32],
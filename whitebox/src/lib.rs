@@ -1,10 +1,14 @@
+#![allow(clippy::needless_range_loop, clippy::too_many_arguments)]
+
 pub mod lattice;
 pub mod create_wb;
 pub mod encrypt;
 pub mod decrypt;
+pub mod context;
 
 pub use crate::lattice::{PubEncData, WhiteData, NTRUVector};
 pub use crate::decrypt::decrypt_message;
+pub use crate::context::DecryptContext;
 pub use crate::encrypt::encrypt_func;
 
 use numpy::ndarray::Array1;
@@ -76,7 +80,7 @@ pub fn decrypt_to_text(white_data: &WhiteData, a1: &NTRUVector, a2: &NTRUVector,
 /// The ASCII text decoded from the binary string.
 pub fn binary_to_text(binary_str: String) -> String {
     let mut binary_str_mut = binary_str;
-    if binary_str_mut.len() % 8 != 0 {
+    if !binary_str_mut.len().is_multiple_of(8) {
         binary_str_mut = format!("{:0<width$}", binary_str_mut, width = binary_str_mut.len() + (8 - binary_str_mut.len() % 8));
     }
 
@@ -87,9 +91,9 @@ pub fn binary_to_text(binary_str: String) -> String {
 
     let ascii_chars: Vec<char> = binary_values
         .iter()
-        .filter_map(|bv| {
+        .map(|bv| {
             let val = u8::from_str_radix(bv, 2).unwrap_or(0);
-            Some(val as char)
+            val as char
         })
         .collect();
 